@@ -0,0 +1,106 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+const SAMPLE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/benches/data/ethbtc_sample.json"
+);
+
+fn bench_load(c: &mut Criterion) {
+    let len = db::Db::new(&SAMPLE).unwrap().get_data_len();
+    let mut group = c.benchmark_group("load");
+    group.throughput(Throughput::Elements(len as u64));
+    group.bench_function("Db::new", |b| {
+        b.iter(|| db::Db::new(black_box(&SAMPLE)).unwrap())
+    });
+    group.bench_function("Db::open_mmap", |b| {
+        b.iter(|| db::Db::open_mmap(black_box(&SAMPLE)).unwrap())
+    });
+    group.finish();
+}
+
+// The sample tiled into a file of DB_BENCH_MB megabytes (300 by default), the
+// size of a long backfill, with ids and times shifted so it stays a valid db.
+// Written once to the temp dir and reused by later runs.
+fn large_file() -> PathBuf {
+    let mb: usize = std::env::var("DB_BENCH_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(300);
+    let path = std::env::temp_dir().join(format!("db_bench_{mb}mb.json"));
+    if path.exists() {
+        return path;
+    }
+    let sample = db::Db::new(&SAMPLE).unwrap();
+    let id_span = sample.get_max_trade_id() - sample.get_min_trade_id() + 1;
+    let time_span = sample.get_max_time_milliseconds() - sample.get_min_time_milliseconds() + 1;
+    let sample_bytes = serde_json::to_vec(&sample.get_all_data_cloned())
+        .unwrap()
+        .len();
+    let tiles = (mb * 1_000_000).div_ceil(sample_bytes) as i64;
+    let partial = path.with_extension("partial");
+    let mut writer = BufWriter::new(std::fs::File::create(&partial).unwrap());
+    writer.write_all(b"[").unwrap();
+    let mut first = true;
+    // newest first, as Db::save writes it
+    for tile in (0..tiles).rev() {
+        for trade in sample.iter_recent_first() {
+            let mut trade = trade.clone();
+            trade.trade_id += tile * id_span;
+            trade.time_milliseconds += tile * time_span;
+            if !first {
+                writer.write_all(b",").unwrap();
+            }
+            first = false;
+            serde_json::to_writer(&mut writer, &trade).unwrap();
+        }
+    }
+    writer.write_all(b"]").unwrap();
+    writer.flush().unwrap();
+    std::fs::rename(&partial, &path).unwrap();
+    path
+}
+
+fn bench_load_large(c: &mut Criterion) {
+    let path = large_file();
+    let len = db::Db::open_mmap(&path).unwrap().get_data_len();
+    let mut group = c.benchmark_group("load_large");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(len as u64));
+    group.bench_function("Db::new", |b| {
+        b.iter(|| db::Db::new(black_box(&path)).unwrap())
+    });
+    group.bench_function("Db::open_mmap", |b| {
+        b.iter(|| db::Db::open_mmap(black_box(&path)).unwrap())
+    });
+    group.finish();
+}
+
+// the per-tick data access of hist_executor's backtest loop: index in
+// chronological order and parse the price
+fn bench_replay(c: &mut Criterion) {
+    let db = db::Db::new(&SAMPLE).unwrap();
+    let mut group = c.benchmark_group("replay");
+    group.throughput(Throughput::Elements(db.get_data_len() as u64));
+    group.bench_function("get_data + get_price", |b| {
+        b.iter(|| {
+            let mut last_price = 0.0;
+            for i in 0..db.get_data_len() {
+                last_price = black_box(db.get_data(i).get_price());
+            }
+            last_price
+        })
+    });
+    group.bench_function("iter_chronological + get_price", |b| {
+        b.iter(|| {
+            db.iter_chronological()
+                .map(|trade| black_box(trade.get_price()))
+                .sum::<f64>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_load, bench_load_large, bench_replay);
+criterion_main!(benches);
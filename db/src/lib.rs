@@ -1,8 +1,25 @@
+use arrow::array::{ArrayRef, BooleanArray, Float64Array, Int64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use memmap2::Mmap;
+use parquet::arrow::ArrowWriter;
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::env;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::future::Future;
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use error_chain::error_chain;
 error_chain! {
@@ -20,12 +37,90 @@ error_chain! {
             description("Got bad code {code}, body {body} when doing request {original_request}")
             display("Got bad code {code}, body {body} when doing request {original_request}")
         }
+        // 5xx, e.g. during binance maintenance; worth retrying
+        ServerError(code: reqwest::StatusCode, body: String, original_request: String) {
+            description("Server error, the request may succeed when retried")
+            display("Got server error {code}, body {body} when doing request {original_request}")
+        }
+        // 4xx; retrying the same request won't help
+        ClientError(code: reqwest::StatusCode, body: String, original_request: String) {
+            description("Client error, the request was rejected")
+            display("Got client error {code}, body {body} when doing request {original_request}")
+        }
+        BinanceApiError(code: i64, msg: String) {
+            description("Binance returned an error body")
+            display("Binance returned error code {}: {}", code, msg)
+        }
+        InvalidTimeRangeError(start_ms: i64, end_ms: i64) {
+            description("Time range is empty or doesn't overlap the data")
+            display("Time range {}-{} is empty or doesn't overlap the data", start_ms, end_ms)
+        }
+        InvalidDownsampleFactorError {
+            description("Downsample factor must be at least 1")
+            display("Downsample factor must be at least 1")
+        }
+        InvalidLimitError(limit: i64) {
+            description("Page limit must be within 1..=1000")
+            display("Page limit must be within 1..=1000, got: '{}'", limit)
+        }
+        MissingSymbolFiltersError(symbol: String) {
+            description("Exchange info has no price/lot size filters for symbol")
+            display("Exchange info has no price/lot size filters for symbol: '{}'", symbol)
+        }
+        NonMonotonicTradeIdError(trade_id: i64) {
+            description("Trade ids are not strictly monotonic")
+            display("Trade ids are not strictly monotonic at trade_id: '{}'", trade_id)
+        }
+        NonMonotonicTimeError(trade_id: i64) {
+            description("Trade timestamps are not monotonic")
+            display("Trade timestamps are not monotonic at trade_id: '{}'", trade_id)
+        }
+        UnparseableFieldError(trade_id: i64, field: String, value: String) {
+            description("Could not parse trade field as a number")
+            display("Could not parse field '{}' with value '{}' at trade_id: '{}'", field, value, trade_id)
+        }
+        InvalidSymbolError(symbol: String) {
+            description("Symbol must be 2 to 20 uppercase letters or digits")
+            display("Symbol must be 2 to 20 uppercase letters or digits, got: '{}'", symbol)
+        }
+        NonPositivePriceError(trade_id: i64, price: String) {
+            description("Trade price is not a positive number")
+            display("Trade price '{}' is not a positive number at trade_id: '{}'", price, trade_id)
+        }
     }
     foreign_links {
         Io(std::io::Error);
         HttpRequest(reqwest::Error);
         JsonDecodeError(serde_json::Error);
         MissingApiKeyInEnv(std::env::VarError);
+        ParseFloat(std::num::ParseFloatError);
+        DateTimeParse(chrono::ParseError);
+        Arrow(arrow::error::ArrowError);
+        Parquet(parquet::errors::ParquetError);
+    }
+}
+
+/*
+    {
+        "code": -1121,
+        "msg": "Invalid symbol."
+    }
+*/
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BinanceResponse<T> {
+    Error { code: i64, msg: String },
+    Data(T),
+}
+
+// decodes either the expected payload or Binance's {code, msg} error body,
+// which can come back even with a successful status code
+pub fn parse_response<T: DeserializeOwned>(data: &str) -> Result<T> {
+    let response: BinanceResponse<T> = serde_json::from_str(data)
+        .chain_err(|| format!("Got json decoder err when decoding text: {data}"))?;
+    match response {
+        BinanceResponse::Error { code, msg } => Err(ErrorKind::BinanceApiError(code, msg).into()),
+        BinanceResponse::Data(data) => Ok(data),
     }
 }
 
@@ -40,7 +135,7 @@ error_chain! {
         "isBestMatch": true
     },
 */
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct HistoricalTrade {
     #[serde(rename = "id")]
     pub trade_id: i64,
@@ -58,23 +153,670 @@ pub struct HistoricalTrade {
     pub is_best_match: bool,
 }
 
+/*
+    {
+        "a": 26129,
+        "p": "0.01633102",
+        "q": "4.70443515",
+        "f": 27781,
+        "l": 27781,
+        "T": 1498793709153,
+        "m": true,
+        "M": true
+    },
+*/
+#[derive(Deserialize, Clone)]
+pub struct AggTrade {
+    #[serde(rename = "a")]
+    pub agg_trade_id: i64,
+    #[serde(rename = "p")]
+    pub price: String,
+    #[serde(rename = "q")]
+    pub quantity: String,
+    #[serde(rename = "T")]
+    pub time_milliseconds: i64,
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+    #[serde(rename = "M")]
+    pub is_best_match: bool,
+}
+
+impl TryFrom<AggTrade> for HistoricalTrade {
+    type Error = Error;
+    // aggregate id takes the place of the trade id, so trade id checks still work
+    fn try_from(trade: AggTrade) -> Result<HistoricalTrade> {
+        let unparseable = |field: &str, value: &String| {
+            ErrorKind::UnparseableFieldError(trade.agg_trade_id, field.to_string(), value.clone())
+        };
+        let price: f64 = trade
+            .price
+            .parse()
+            .map_err(|_| unparseable("p", &trade.price))?;
+        let quantity: f64 = trade
+            .quantity
+            .parse()
+            .map_err(|_| unparseable("q", &trade.quantity))?;
+        Ok(HistoricalTrade {
+            trade_id: trade.agg_trade_id,
+            quote_quantity: format!("{:.8}", price * quantity),
+            price: trade.price,
+            quantity: trade.quantity,
+            time_milliseconds: trade.time_milliseconds,
+            is_buyer_maker: trade.is_buyer_maker,
+            is_best_match: trade.is_best_match,
+        })
+    }
+}
+
 impl HistoricalTrade {
+    // panics on a price that doesn't parse; use try_price on data that hasn't
+    // been through Db::check_prices or Db::validate
     pub fn get_price(&self) -> f64 {
         self.price.parse().unwrap()
     }
+    // the price, if it parses and is positive
+    pub fn try_price(&self) -> Result<f64> {
+        let price: f64 = self.price.parse().map_err(|_| {
+            ErrorKind::UnparseableFieldError(self.trade_id, "price".to_string(), self.price.clone())
+        })?;
+        // NaN and inf parse fine, but are no more a price than 0 is
+        if !price.is_finite() || price <= 0.0 {
+            return Err(ErrorKind::NonPositivePriceError(self.trade_id, self.price.clone()).into());
+        }
+        Ok(price)
+    }
+    pub fn get_quantity(&self) -> f64 {
+        self.quantity.parse().unwrap()
+    }
+    pub fn trade_datetime(&self) -> DateTime<Utc> {
+        datetime_from_milliseconds(self.time_milliseconds)
+    }
+}
+
+pub fn datetime_from_milliseconds(milliseconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(milliseconds).unwrap()
 }
 
+// e.g. 2022-05-15T11:32:27.356Z
+pub fn format_milliseconds(milliseconds: i64) -> String {
+    datetime_from_milliseconds(milliseconds).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+// Accepts RFC 3339 (2022-05-15T11:32:27Z, 2022-05-15T13:32:27+02:00), or, taken
+// as UTC, 2022-05-15T11:32:27, 2022-05-15 11:32:27 and 2022-05-15.
+pub fn parse_datetime(s: &str) -> Result<i64> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Ok(datetime.timestamp_millis());
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        })?;
+    Ok(Utc.from_utc_datetime(&naive).timestamp_millis())
+}
+
+// milliseconds since epoch, or anything parse_datetime accepts
+pub fn parse_time(s: &str) -> Result<i64> {
+    if let Ok(milliseconds) = s.parse::<i64>() {
+        return Ok(milliseconds);
+    }
+    parse_datetime(s)
+}
+
+// what Db::check_prices does with a trade whose price fails try_price
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BadPricePolicy {
+    Reject, // fail with the error of the first such trade
+    Skip,   // drop such trades
+}
+
+pub struct Kline {
+    pub open_time_milliseconds: i64, // start of the bucket
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64, // sum of trade quantities
+    pub trade_count: usize,
+}
+
+// trade quantities, in the pair's base asset
+pub struct QuantityStats {
+    pub mean: f64,
+    pub median: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+// value at fraction q of sorted, linear interpolation between closest ranks
+fn sorted_quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+// hex HMAC-SHA256 of the query string, sent as the `signature` parameter of
+// signed endpoints
+pub fn sign_query(secret: &str, query: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(query.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[derive(Deserialize)]
+struct ServerTime {
+    #[serde(rename = "serverTime")]
+    server_time_milliseconds: i64,
+}
+
+pub fn local_time_milliseconds() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
+pub async fn fetch_server_time() -> Result<i64> {
+    let query = "https://api.binance.com/api/v3/time".to_string();
+    let res = reqwest::Client::new().get(&query).send().await?;
+    let status = res.status();
+    let data = res.text().await?;
+    if !status.is_success() {
+        return Err(status_error(status, data, query));
+    }
+    let server_time: ServerTime = parse_response(&data)?;
+    Ok(server_time.server_time_milliseconds)
+}
+
+// server time minus local time; add it to local timestamps of signed requests
+// so they fall within Binance's recvWindow
+pub async fn fetch_clock_offset() -> Result<i64> {
+    let before = local_time_milliseconds();
+    let server_time = fetch_server_time().await?;
+    let after = local_time_milliseconds();
+    Ok(server_time - (before + after) / 2)
+}
+
+// Ordering contract: trades are stored sorted by trade_id, newest first, which
+// is also the order save writes and get_all_data_cloned returns. Positions
+// passed to or returned by get_data, get_window, index_range_for_time and
+// split_at_fraction are chronological instead: 0 is the oldest trade. Min
+// accessors report the oldest trade, max accessors the newest. Everything
+// that builds a Db goes through sort_newest_first or Db::from, which rejects
+// any other order.
 pub struct Db {
     data: Vec<HistoricalTrade>, // from most recent to least recent
 }
 
+fn sort_newest_first(data: &mut [HistoricalTrade]) {
+    data.sort_by_key(|t| std::cmp::Reverse(t.trade_id));
+}
+
+// Binance spot limits; see https://binance-docs.github.io/apidocs/spot/en/#limits
+pub const REQUEST_WEIGHT_PER_MINUTE: i64 = 1200;
+pub const HISTORICAL_TRADES_WEIGHT: i64 = 5;
+pub const MAX_PAGE_LIMIT: i64 = 1000;
+// used weight at which fetchers wait for the next minute window
+pub const DEFAULT_WEIGHT_THRESHOLD: i64 = REQUEST_WEIGHT_PER_MINUTE * 4 / 5;
+
+fn check_limit(limit: i64) -> Result<()> {
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(ErrorKind::InvalidLimitError(limit).into());
+    }
+    Ok(())
+}
+
+// cheap request to confirm the api is reachable
+pub async fn ping() -> Result<()> {
+    let query = "https://api.binance.com/api/v3/ping".to_string();
+    let res = reqwest::Client::new().get(&query).send().await?;
+    let status = res.status();
+    if !status.is_success() {
+        let data = res.text().await?;
+        return Err(status_error(status, data, query));
+    }
+    Ok(())
+}
+
+// milliseconds until the next minute window if used_weight reached threshold
+pub fn throttle_delay_milliseconds(used_weight: i64, threshold: i64, now_ms: i64) -> Option<i64> {
+    if used_weight < threshold {
+        return None;
+    }
+    Some(60_000 - now_ms.rem_euclid(60_000))
+}
+
+// ServerError and ClientError for 5xx and 4xx, BadStatusCodeError for anything else
+fn status_error(status: reqwest::StatusCode, body: String, query: String) -> Error {
+    if status.is_server_error() {
+        ErrorKind::ServerError(status, body, query).into()
+    } else if status.is_client_error() {
+        ErrorKind::ClientError(status, body, query).into()
+    } else {
+        ErrorKind::BadStatusCodeError(status, body, query).into()
+    }
+}
+
+// retries of a page that failed with a ServerError, waiting
+// SERVER_ERROR_BACKOFF_MS, then twice as long after every further failure
+pub const MAX_SERVER_ERROR_RETRIES: u32 = 5;
+const SERVER_ERROR_BACKOFF_MS: u64 = 1000;
+
+fn used_weight(headers: &reqwest::header::HeaderMap) -> Option<i64> {
+    headers
+        .get("X-MBX-USED-WEIGHT-1m")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+async fn throttle(used_weight: Option<i64>, threshold: i64) {
+    let delay = used_weight
+        .and_then(|used| throttle_delay_milliseconds(used, threshold, local_time_milliseconds()));
+    if let Some(delay) = delay {
+        tokio::time::sleep(Duration::from_millis(delay as u64)).await;
+    }
+}
+
+// a trading pair like ETHBTC, validated so it is safe to put into a url
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol(String);
+
+impl Symbol {
+    pub fn new(symbol: &str) -> Result<Symbol> {
+        let valid_chars = symbol
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        if !valid_chars || symbol.len() < 2 || symbol.len() > 20 {
+            return Err(ErrorKind::InvalidSymbolError(symbol.to_string()).into());
+        }
+        Ok(Symbol(symbol.to_string()))
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = Error;
+    fn from_str(symbol: &str) -> Result<Symbol> {
+        Symbol::new(symbol)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// source of trade pages for Db::load_more_data
+pub trait TradeFetcher {
+    // up to limit trades with trade_id >= from_id
+    fn fetch(
+        &self,
+        symbol: &Symbol,
+        from_id: i64,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<HistoricalTrade>>> + Send;
+}
+
+// historicalTrades endpoint; needs BINANCE_API_KEY.
+// Sleeps after a request if the used weight reported by binance reached weight_threshold.
+pub struct HistoricalTradesFetcher {
+    pub weight_threshold: i64,
+}
+
+impl TradeFetcher for HistoricalTradesFetcher {
+    async fn fetch(
+        &self,
+        symbol: &Symbol,
+        from_id: i64,
+        limit: i64,
+    ) -> Result<Vec<HistoricalTrade>> {
+        let query = format!("https://api.binance.com/api/v3/historicalTrades?symbol={symbol}&limit={limit}&fromId={from_id}");
+        let client = reqwest::Client::new();
+        let api_key = env::var("BINANCE_API_KEY").chain_err(|| ErrorKind::ApiKeyNotFoundError)?;
+        let res = client
+            .get(&query)
+            .header("X-MBX-APIKEY", api_key)
+            .send()
+            .await?;
+        let status = res.status();
+        let used_weight = used_weight(res.headers());
+        let data = res.text().await?;
+        if !status.is_success() {
+            return Err(status_error(status, data, query));
+        }
+        throttle(used_weight, self.weight_threshold).await;
+        parse_response(&data)
+    }
+}
+
+// aggTrades endpoint; much smaller responses, same price action, no api key needed
+pub struct AggTradesFetcher {
+    pub weight_threshold: i64,
+}
+
+impl TradeFetcher for AggTradesFetcher {
+    async fn fetch(
+        &self,
+        symbol: &Symbol,
+        from_id: i64,
+        limit: i64,
+    ) -> Result<Vec<HistoricalTrade>> {
+        let query = format!(
+            "https://api.binance.com/api/v3/aggTrades?symbol={symbol}&limit={limit}&fromId={from_id}"
+        );
+        self.get(query).await
+    }
+}
+
+// longest startTime..endTime span the aggTrades endpoint accepts
+pub const MAX_AGG_TRADES_WINDOW_MS: i64 = 3600 * 1000;
+
+// start_ms..end_ms cut into consecutive half-open windows of at most chunk_ms
+pub fn time_chunks(start_ms: i64, end_ms: i64, chunk_ms: i64) -> Vec<(i64, i64)> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = start_ms;
+    while chunk_start < end_ms {
+        let chunk_end = i64::min(chunk_start + chunk_ms, end_ms);
+        chunks.push((chunk_start, chunk_end));
+        chunk_start = chunk_end;
+    }
+    chunks
+}
+
+// Drops the trades of a page at or after end_ms; returns the fromId of the
+// next page, or None if nothing before end_ms can come after this page.
+fn clip_page(page: &mut Vec<HistoricalTrade>, end_ms: i64, limit: i64) -> Option<i64> {
+    let full = page.len() as i64 == limit;
+    let next_id = page.last().map(|trade| trade.trade_id + 1);
+    let len = page.len();
+    page.retain(|trade| trade.time_milliseconds < end_ms);
+    if full && page.len() == len {
+        next_id
+    } else {
+        None
+    }
+}
+
+impl AggTradesFetcher {
+    // up to limit trades with start_ms <= time < end_ms; the window must not
+    // be longer than MAX_AGG_TRADES_WINDOW_MS
+    pub async fn fetch_time_window(
+        &self,
+        symbol: &Symbol,
+        start_ms: i64,
+        end_ms: i64,
+        limit: i64,
+    ) -> Result<Vec<HistoricalTrade>> {
+        // endTime is inclusive
+        let query = format!(
+            "https://api.binance.com/api/v3/aggTrades?symbol={symbol}&limit={limit}&startTime={start_ms}&endTime={}",
+            end_ms - 1
+        );
+        self.get(query).await
+    }
+    // all trades with start_ms <= time < end_ms, fetched in hour long windows,
+    // each paged by id when it holds more than a page of trades
+    pub async fn fetch_time_range(
+        &self,
+        symbol: &Symbol,
+        start_ms: i64,
+        end_ms: i64,
+    ) -> Result<Db> {
+        let mut trades = Vec::new();
+        for (chunk_start, chunk_end) in time_chunks(start_ms, end_ms, MAX_AGG_TRADES_WINDOW_MS) {
+            let mut page = self
+                .fetch_time_window(symbol, chunk_start, chunk_end, MAX_PAGE_LIMIT)
+                .await?;
+            // by fromId after the first page, since a page can end partway
+            // through a millisecond that later trades still share
+            loop {
+                let next_id = clip_page(&mut page, chunk_end, MAX_PAGE_LIMIT);
+                trades.append(&mut page);
+                match next_id {
+                    Some(from_id) => page = self.fetch(symbol, from_id, MAX_PAGE_LIMIT).await?,
+                    None => break,
+                }
+            }
+        }
+        sort_newest_first(&mut trades);
+        trades.dedup_by_key(|trade| trade.trade_id);
+        Db::from(trades)
+    }
+    async fn get(&self, query: String) -> Result<Vec<HistoricalTrade>> {
+        let client = reqwest::Client::new();
+        let res = client.get(&query).send().await?;
+        let status = res.status();
+        let used_weight = used_weight(res.headers());
+        let data = res.text().await?;
+        if !status.is_success() {
+            return Err(status_error(status, data, query));
+        }
+        throttle(used_weight, self.weight_threshold).await;
+        parse_agg_trades(&data)
+    }
+}
+
+// serves pages out of trades held in memory, for running the backfill logic offline
+pub struct InMemoryTradeFetcher {
+    trades: Vec<HistoricalTrade>, // sorted by trade_id, oldest first
+}
+
+impl InMemoryTradeFetcher {
+    pub fn new(mut trades: Vec<HistoricalTrade>) -> InMemoryTradeFetcher {
+        trades.sort_by_key(|trade| trade.trade_id);
+        InMemoryTradeFetcher { trades }
+    }
+}
+
+impl TradeFetcher for InMemoryTradeFetcher {
+    async fn fetch(
+        &self,
+        _symbol: &Symbol,
+        from_id: i64,
+        limit: i64,
+    ) -> Result<Vec<HistoricalTrade>> {
+        let start = self
+            .trades
+            .partition_point(|trade| trade.trade_id < from_id);
+        Ok(self.trades[start..]
+            .iter()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+fn is_gzip<P: AsRef<Path>>(filename: &P) -> bool {
+    filename
+        .as_ref()
+        .extension()
+        .is_some_and(|extension| extension == "gz")
+}
+
+// transparently decompresses files ending in .gz
+fn open_reader<P: AsRef<Path>>(filename: &P) -> Result<Box<dyn BufRead>> {
+    let file = File::open(filename)?;
+    if is_gzip(filename) {
+        return Ok(Box::new(BufReader::new(GzDecoder::new(file))));
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// what summarize_file reads of each trade; serde skips every other field
+#[derive(Deserialize)]
+struct TradeKey {
+    #[serde(rename = "id")]
+    trade_id: i64,
+    #[serde(rename = "time")]
+    time_milliseconds: i64,
+}
+
+// Same numbers as the Db getters report, collected by summarize_file
+pub struct FileSummary {
+    pub count: usize,
+    pub min_trade_id: i64,
+    pub max_trade_id: i64,
+    pub min_time_milliseconds: i64,
+    pub max_time_milliseconds: i64,
+}
+
+impl FileSummary {
+    fn empty() -> FileSummary {
+        FileSummary {
+            count: 0,
+            min_trade_id: 0,
+            max_trade_id: 0,
+            min_time_milliseconds: 0,
+            max_time_milliseconds: 0,
+        }
+    }
+    fn add(&mut self, key: TradeKey) {
+        if self.count == 0 {
+            self.min_trade_id = key.trade_id;
+            self.max_trade_id = key.trade_id;
+            self.min_time_milliseconds = key.time_milliseconds;
+            self.max_time_milliseconds = key.time_milliseconds;
+        }
+        self.count += 1;
+        self.min_trade_id = i64::min(self.min_trade_id, key.trade_id);
+        self.max_trade_id = i64::max(self.max_trade_id, key.trade_id);
+        self.min_time_milliseconds = i64::min(self.min_time_milliseconds, key.time_milliseconds);
+        self.max_time_milliseconds = i64::max(self.max_time_milliseconds, key.time_milliseconds);
+    }
+}
+
+// folds the elements of a json array into a FileSummary one at a time
+struct SummaryVisitor;
+
+impl<'de> Visitor<'de> for SummaryVisitor {
+    type Value = FileSummary;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of trades")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<FileSummary, A::Error> {
+        let mut summary = FileSummary::empty();
+        while let Some(key) = seq.next_element::<TradeKey>()? {
+            summary.add(key);
+        }
+        Ok(summary)
+    }
+}
+
+// Count, trade id range and time span of a json array or ndjson file (either
+// optionally gzipped), streamed trade by trade instead of loaded into a Db.
+pub fn summarize_file<P: AsRef<Path>>(filename: &P) -> Result<FileSummary> {
+    let mut reader = open_reader(filename)?;
+    // a json array starts with '[', ndjson with the '{' of its first trade
+    let is_array = loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break false;
+        }
+        match buf.iter().position(|c| !c.is_ascii_whitespace()) {
+            Some(i) => {
+                let is_array = buf[i] == b'[';
+                reader.consume(i);
+                break is_array;
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    };
+    let summary = if is_array {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        serde::Deserializer::deserialize_seq(&mut deserializer, SummaryVisitor)?
+    } else {
+        let mut summary = FileSummary::empty();
+        for key in serde_json::Deserializer::from_reader(reader).into_iter::<TradeKey>() {
+            summary.add(key?);
+        }
+        summary
+    };
+    if summary.count == 0 {
+        return Err(ErrorKind::EmptyDbError.into());
+    }
+    Ok(summary)
+}
+
+// compresses when the output path ends in .gz; call finish_writer when done
+enum DbWriter {
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzEncoder<File>>),
+}
+
+impl DbWriter {
+    fn create<P: AsRef<Path>>(filename: &P) -> Result<DbWriter> {
+        let file = File::create(filename)?;
+        if is_gzip(filename) {
+            let encoder = GzEncoder::new(file, Compression::default());
+            return Ok(DbWriter::Gzip(BufWriter::new(encoder)));
+        }
+        Ok(DbWriter::Plain(BufWriter::new(file)))
+    }
+    fn finish(self) -> Result<()> {
+        match self {
+            DbWriter::Plain(mut writer) => writer.flush()?,
+            DbWriter::Gzip(writer) => {
+                let encoder = writer.into_inner().map_err(|e| e.into_error())?;
+                encoder.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for DbWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DbWriter::Plain(writer) => writer.write(buf),
+            DbWriter::Gzip(writer) => writer.write(buf),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DbWriter::Plain(writer) => writer.flush(),
+            DbWriter::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
 impl Db {
+    // newest first, as stored
     pub fn get_all_data_cloned(&self) -> Vec<HistoricalTrade> {
         self.data.clone()
     }
+    // idx is chronological, 0 for the oldest trade
     pub fn get_data(&self, idx: usize) -> &HistoricalTrade {
         &self.data[self.data.len() - idx - 1] // inverse, because data is stored recent-to-latest
     }
+    // trades with chronological index in start_id..finish_id (as used by get_data),
+    // stored like the rest of the db from most recent to least recent
+    pub fn get_window(&self, start_id: usize, finish_id: usize) -> &[HistoricalTrade] {
+        &self.data[self.data.len() - finish_id..self.data.len() - start_id]
+    }
+    pub fn iter_chronological(&self) -> impl DoubleEndedIterator<Item = &HistoricalTrade> {
+        self.data.iter().rev()
+    }
+    pub fn iter_recent_first(&self) -> impl DoubleEndedIterator<Item = &HistoricalTrade> {
+        self.data.iter()
+    }
     pub fn get_min_trade_id(&self) -> i64 {
         self.data.last().unwrap().trade_id
     }
@@ -84,44 +826,175 @@ impl Db {
     pub fn get_min_time_milliseconds(&self) -> i64 {
         self.data.last().unwrap().time_milliseconds
     }
+    pub fn get_max_time_milliseconds(&self) -> i64 {
+        self.data[0].time_milliseconds
+    }
     pub fn get_data_len(&self) -> usize {
         self.data.len()
     }
+    // chronological index range (as used by get_data) of trades with
+    // start_ms <= time < end_ms, found by binary search
+    pub fn index_range_for_time(&self, start_ms: i64, end_ms: i64) -> Result<(usize, usize)> {
+        let start_id = self.first_index_at_or_after(start_ms);
+        let finish_id = self.first_index_at_or_after(end_ms);
+        if start_ms >= end_ms || start_id >= finish_id {
+            return Err(ErrorKind::InvalidTimeRangeError(start_ms, end_ms).into());
+        }
+        Ok((start_id, finish_id))
+    }
+    // chronological index of the first trade with time >= ms, or len if there is none
+    fn first_index_at_or_after(&self, ms: i64) -> usize {
+        // data is sorted newest first, so "time >= x" holds for a prefix
+        self.data.len() - self.data.partition_point(|t| t.time_milliseconds >= ms)
+    }
+    // price of the last trade at or before ms, None if ms precedes all data
+    pub fn price_at_time(&self, ms: i64) -> Option<f64> {
+        // newest first, so trades after ms form a prefix
+        let idx = self.data.partition_point(|t| t.time_milliseconds > ms);
+        self.data.get(idx).map(|trade| trade.get_price())
+    }
+    // for many index_range_for_time queries with sliding windows
+    pub fn window_cursor(&self) -> WindowCursor<'_> {
+        WindowCursor {
+            db: self,
+            start_ms: i64::MIN,
+            end_ms: i64::MIN,
+            start_id: 0,
+            finish_id: 0,
+        }
+    }
+    // number of load_more_data calls needed to go back to target_trade_id
+    pub fn pages_to_reach(&self, target_trade_id: i64, limit: i64) -> Result<i64> {
+        check_limit(limit)?;
+        let remaining = self.get_min_trade_id() - target_trade_id;
+        if remaining <= 0 {
+            return Ok(0);
+        }
+        Ok((remaining + limit - 1) / limit)
+    }
+    // MAX_PAGE_LIMIT-trade pages needed to go back to target_ms, assuming trades
+    // keep arriving at the average rate observed over the data
+    pub fn estimate_pages_for_time(&self, target_ms: i64) -> usize {
+        let remaining_ms = self.get_min_time_milliseconds() - target_ms;
+        if remaining_ms <= 0 {
+            return 0;
+        }
+        // ids rather than len, so gaps from downsampling don't skew the rate
+        let trades = (self.get_max_trade_id() - self.get_min_trade_id()) as f64;
+        let span_ms = i64::max(
+            self.get_max_time_milliseconds() - self.get_min_time_milliseconds(),
+            1,
+        );
+        let remaining_trades = trades / span_ms as f64 * remaining_ms as f64;
+        (remaining_trades / MAX_PAGE_LIMIT as f64).ceil() as usize
+    }
+    // the file may hold trades in any order; they are sorted newest first
     pub fn new<P: AsRef<Path>>(filename: &P) -> Result<Db> {
-        let file = File::open(filename)?;
-        let reader = BufReader::new(file);
+        let reader = open_reader(filename)?;
         let mut deserialized: Vec<HistoricalTrade> = serde_json::from_reader(reader)?;
-        if deserialized.len() == 0 {
+        if deserialized.is_empty() {
+            return Err(ErrorKind::EmptyDbError.into());
+        }
+        sort_newest_first(&mut deserialized);
+        Ok(Db { data: deserialized })
+    }
+    // Same result as new, but parses straight out of a memory map of the file
+    // instead of through a buffered reader, which is faster for big files. It
+    // saves no memory: the trades are parsed into owned strings just as new
+    // does, so the whole db still lives on the heap. Gzipped files fall back to new.
+    // The file must not be modified while it is being read: on unix that is
+    // undefined behaviour rather than an error, and on windows the mapping
+    // keeps the file locked until parsing is done.
+    pub fn open_mmap<P: AsRef<Path>>(filename: &P) -> Result<Db> {
+        if is_gzip(filename) {
+            return Db::new(filename);
+        }
+        let file = File::open(filename)?;
+        // safety: see above, the caller guarantees nobody writes the file meanwhile
+        let mmap = unsafe { Mmap::map(&file)? };
+        let mut deserialized: Vec<HistoricalTrade> = serde_json::from_slice(&mmap)?;
+        if deserialized.is_empty() {
             return Err(ErrorKind::EmptyDbError.into());
         }
-        deserialized.sort_by(|a, b| b.trade_id.cmp(&a.trade_id));
+        sort_newest_first(&mut deserialized);
         Ok(Db { data: deserialized })
     }
+    // data must already be sorted by trade_id, newest first, as get_all_data_cloned
+    // returns it; any other order is an error. Use from_trades_unsorted otherwise.
     pub fn from(data: Vec<HistoricalTrade>) -> Result<Db> {
-        if data.len() == 0 {
+        if data.is_empty() {
             return Err(ErrorKind::EmptyDbError.into());
         }
-        Ok(Db { data: data })
+        // equal ids are left to find_duplicates and dedup
+        if let Some(pair) = data
+            .windows(2)
+            .find(|pair| pair[0].trade_id < pair[1].trade_id)
+        {
+            return Err(ErrorKind::NonMonotonicTradeIdError(pair[1].trade_id).into());
+        }
+        Ok(Db { data })
     }
-    pub async fn load_more_data(&mut self, symbol: &str) -> Result<()> {
-        let limit = 1000;
-        let from_id = self.get_min_trade_id() - limit;
-        let query = format!("https://api.binance.com/api/v3/historicalTrades?symbol={symbol}&limit={limit}&fromId={from_id}");
-        let client = reqwest::Client::new();
-        let api_key = env::var("BINANCE_API_KEY").chain_err(|| ErrorKind::ApiKeyNotFoundError)?;
-        let res = client
-            .get(&query)
-            .header("X-MBX-APIKEY", api_key)
-            .send()
-            .await?;
-        let status = res.status();
-        let data = res.text().await?;
-        if !status.is_success() {
-            error_chain::bail!(ErrorKind::BadStatusCodeError(status, data, query));
+    // trades in any order, sorted newest first the way new does
+    pub fn from_trades_unsorted(mut data: Vec<HistoricalTrade>) -> Result<Db> {
+        sort_newest_first(&mut data);
+        Db::from(data)
+    }
+    // drops all but the max_trades most recent trades
+    pub fn trim_to_recent(&mut self, max_trades: usize) -> Result<()> {
+        if max_trades == 0 {
+            return Err(ErrorKind::EmptyDbError.into());
         }
-        let mut new_data: Vec<HistoricalTrade> = serde_json::from_str(&data)
-            .chain_err(|| format!("Got json decoder err when decoding text: {data}"))?;
-        if new_data.len() == 0 {
+        self.data.truncate(max_trades);
+        Ok(())
+    }
+    // trade_ids that appear more than once, each reported once
+    pub fn find_duplicates(&self) -> Vec<i64> {
+        let mut duplicates: Vec<i64> = self
+            .data
+            .windows(2)
+            .filter(|pair| pair[0].trade_id == pair[1].trade_id)
+            .map(|pair| pair[0].trade_id)
+            .collect();
+        duplicates.dedup();
+        duplicates
+    }
+    // keeps the first occurrence of every trade_id
+    pub fn dedup(&mut self) {
+        self.data.dedup_by_key(|trade| trade.trade_id);
+    }
+    // union of both dbs, e.g. for separately downloaded overlapping ranges;
+    // a trade_id present in both is kept once
+    pub fn merge(a: Db, b: Db) -> Result<Db> {
+        let mut data = a.data;
+        data.extend(b.data);
+        sort_newest_first(&mut data);
+        data.dedup_by_key(|trade| trade.trade_id);
+        Db::from(data)
+    }
+    // fetches the limit trades preceding the oldest one in the db
+    pub async fn load_more_data<F: TradeFetcher>(
+        &mut self,
+        fetcher: &F,
+        symbol: &Symbol,
+        limit: i64,
+    ) -> Result<()> {
+        check_limit(limit)?;
+        let from_id = self.get_min_trade_id() - limit;
+        let mut retries = 0;
+        let new_data = loop {
+            match fetcher.fetch(symbol, from_id, limit).await {
+                Err(Error(ErrorKind::ServerError(..), _)) if retries < MAX_SERVER_ERROR_RETRIES => {
+                    let delay = SERVER_ERROR_BACKOFF_MS << retries;
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    retries += 1;
+                }
+                result => break result?,
+            }
+        };
+        self.extend_with_older(new_data)
+    }
+    fn extend_with_older(&mut self, mut new_data: Vec<HistoricalTrade>) -> Result<()> {
+        if new_data.is_empty() {
             return Err(ErrorKind::EmptyDbError.into());
         }
         if new_data[0].trade_id >= self.get_min_trade_id() {
@@ -131,13 +1004,1623 @@ impl Db {
             )
             .into());
         }
-        new_data.sort_by(|a, b| b.trade_id.cmp(&a.trade_id));
-        self.data.extend(new_data.drain(..));
+        sort_newest_first(&mut new_data);
+        self.data.append(&mut new_data);
+        Ok(())
+    }
+    // earlier (time < boundary_ms) and later (time >= boundary_ms) trades as
+    // independent dbs, e.g. for fitting on one and evaluating on the other
+    pub fn split_at_time(&self, boundary_ms: i64) -> Result<(Db, Db)> {
+        self.split_at_index(self.first_index_at_or_after(boundary_ms))
+    }
+    // same as split_at_time, with the first fraction of trades in the earlier db
+    pub fn split_at_fraction(&self, fraction: f64) -> Result<(Db, Db)> {
+        self.split_at_index((self.data.len() as f64 * fraction).round() as usize)
+    }
+    // chronological index of the first trade of the later db
+    fn split_at_index(&self, idx: usize) -> Result<(Db, Db)> {
+        let idx = usize::min(idx, self.data.len());
+        let (later, earlier) = self.data.split_at(self.data.len() - idx);
+        Ok((Db::from(earlier.to_vec())?, Db::from(later.to_vec())?))
+    }
+    // (lower price of bucket, traded base quantity) for num_buckets equal-width
+    // price buckets spanning min to max price, lowest first
+    pub fn volume_profile(&self, num_buckets: usize) -> Vec<(f64, f64)> {
+        if num_buckets == 0 {
+            return Vec::new();
+        }
+        let prices = self.data.iter().map(|trade| trade.get_price());
+        let min_price = prices.clone().fold(f64::INFINITY, f64::min);
+        let max_price = prices.fold(f64::NEG_INFINITY, f64::max);
+        let width = (max_price - min_price) / num_buckets as f64;
+        let mut profile: Vec<(f64, f64)> = (0..num_buckets)
+            .map(|i| (min_price + width * i as f64, 0.0))
+            .collect();
+        for trade in &self.data {
+            let bucket = if width > 0.0 {
+                ((trade.get_price() - min_price) / width) as usize
+            } else {
+                0
+            };
+            // the max price lands exactly on the upper edge of the last bucket
+            profile[usize::min(bucket, num_buckets - 1)].1 += trade.get_quantity();
+        }
+        profile
+    }
+    // distribution of trade sizes, e.g. to pick realistic order sizes for backtests
+    pub fn quantity_stats(&self) -> QuantityStats {
+        let mut quantities: Vec<f64> = self.data.iter().map(|t| t.get_quantity()).collect();
+        quantities.sort_by(|a, b| a.total_cmp(b));
+        QuantityStats {
+            mean: quantities.iter().sum::<f64>() / quantities.len() as f64,
+            median: sorted_quantile(&quantities, 0.5),
+            p5: sorted_quantile(&quantities, 0.05),
+            p25: sorted_quantile(&quantities, 0.25),
+            p75: sorted_quantile(&quantities, 0.75),
+            p95: sorted_quantile(&quantities, 0.95),
+            p99: sorted_quantile(&quantities, 0.99),
+            min: quantities[0],
+            max: quantities[quantities.len() - 1],
+        }
+    }
+    // (boundary, price of the last trade at or before it) for every multiple of
+    // interval_ms from the first trade to the last, oldest first; boundaries
+    // without new trades carry the previous price forward
+    pub fn resample(&self, interval_ms: i64) -> Vec<(i64, f64)> {
+        if interval_ms <= 0 {
+            return Vec::new();
+        }
+        let min_time = self.get_min_time_milliseconds();
+        let mut boundary = (min_time + interval_ms - 1).div_euclid(interval_ms) * interval_ms;
+        let mut samples = Vec::new();
+        let mut trades = self.iter_chronological().peekable();
+        let mut last_price = None;
+        while boundary <= self.get_max_time_milliseconds() {
+            while let Some(trade) = trades.next_if(|t| t.time_milliseconds <= boundary) {
+                last_price = Some(trade.get_price());
+            }
+            if let Some(price) = last_price {
+                samples.push((boundary, price));
+            }
+            boundary += interval_ms;
+        }
+        samples
+    }
+    // keeps every factor-th trade in chronological order, plus the most recent
+    // one, so both endpoints of the data survive
+    pub fn downsample(&self, factor: usize) -> Result<Db> {
+        if factor == 0 {
+            return Err(ErrorKind::InvalidDownsampleFactorError.into());
+        }
+        let len = self.data.len();
+        let data: Vec<HistoricalTrade> = self
+            .data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i == 0 || (len - 1 - i).is_multiple_of(factor))
+            .map(|(_, trade)| trade.clone())
+            .collect();
+        Db::from(data)
+    }
+    // (buy volume - sell volume) / total volume, in base, over the last window
+    // trades, oldest first: element i ends at chronological index i + window - 1.
+    // A buyer maker means the seller crossed the spread, so those trades count
+    // as sell volume and the rest as buy volume. A window without volume is 0.
+    pub fn order_flow_imbalance(&self, window: usize) -> Vec<f64> {
+        let mut imbalances = Vec::new();
+        if window == 0 {
+            return imbalances;
+        }
+        // signed volume, positive for aggressive buys
+        let flows: Vec<(f64, f64)> = self
+            .iter_chronological()
+            .map(|trade| {
+                let quantity = trade.get_quantity();
+                let signed = if trade.is_buyer_maker {
+                    -quantity
+                } else {
+                    quantity
+                };
+                (signed, quantity)
+            })
+            .collect();
+        let (mut net, mut total) = (0.0, 0.0);
+        for (i, &(signed, quantity)) in flows.iter().enumerate() {
+            net += signed;
+            total += quantity;
+            if i >= window {
+                let (old_signed, old_quantity) = flows[i - window];
+                net -= old_signed;
+                total -= old_quantity;
+            }
+            if i + 1 < window {
+                continue;
+            }
+            imbalances.push(if total > 0.0 { net / total } else { 0.0 });
+        }
+        imbalances
+    }
+    // (bucket start, number of trades) for the same buckets as to_candles, oldest
+    // first, but with quiet buckets between the first and last trade kept as 0
+    pub fn trades_per_interval(&self, interval_ms: i64) -> Vec<(i64, usize)> {
+        if interval_ms <= 0 {
+            return Vec::new();
+        }
+        let first = self.get_min_time_milliseconds().div_euclid(interval_ms);
+        let last = self.get_max_time_milliseconds().div_euclid(interval_ms);
+        let mut counts: Vec<(i64, usize)> = (first..=last)
+            .map(|bucket| (bucket * interval_ms, 0))
+            .collect();
+        for trade in &self.data {
+            let bucket = trade.time_milliseconds.div_euclid(interval_ms);
+            counts[(bucket - first) as usize].1 += 1;
+        }
+        counts
+    }
+    // Buckets trades by time_milliseconds / interval_ms, oldest first.
+    // Buckets without trades are skipped rather than carried forward.
+    pub fn to_candles(&self, interval_ms: i64) -> Vec<Kline> {
+        if interval_ms <= 0 {
+            return Vec::new();
+        }
+        let mut candles: Vec<Kline> = Vec::new();
+        for trade in self.iter_chronological() {
+            let open_time = trade.time_milliseconds.div_euclid(interval_ms) * interval_ms;
+            let price = trade.get_price();
+            let quantity = trade.get_quantity();
+            match candles.last_mut() {
+                Some(candle) if candle.open_time_milliseconds == open_time => {
+                    candle.high = f64::max(candle.high, price);
+                    candle.low = f64::min(candle.low, price);
+                    candle.close = price;
+                    candle.volume += quantity;
+                    candle.trade_count += 1;
+                }
+                _ => candles.push(Kline {
+                    open_time_milliseconds: open_time,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume: quantity,
+                    trade_count: 1,
+                }),
+            }
+        }
+        candles
+    }
+    // Makes get_price safe to call on every trade, see BadPricePolicy; returns
+    // the number of trades skipped. Fails with EmptyDbError if all are skipped.
+    pub fn check_prices(&mut self, policy: BadPricePolicy) -> Result<usize> {
+        let before = self.data.len();
+        match policy {
+            BadPricePolicy::Reject => {
+                for trade in &self.data {
+                    trade.try_price()?;
+                }
+            }
+            BadPricePolicy::Skip => self.data.retain(|trade| trade.try_price().is_ok()),
+        }
+        if self.data.is_empty() {
+            return Err(ErrorKind::EmptyDbError.into());
+        }
+        Ok(before - self.data.len())
+    }
+    // checks the invariants backtests rely on; reports the first offending trade
+    pub fn validate(&self) -> Result<()> {
+        if self.data.is_empty() {
+            return Err(ErrorKind::EmptyDbError.into());
+        }
+        for (i, trade) in self.data.iter().enumerate() {
+            let fields = [
+                ("price", &trade.price),
+                ("qty", &trade.quantity),
+                ("quoteQty", &trade.quote_quantity),
+            ];
+            for (field, value) in fields {
+                if value.parse::<f64>().is_err() {
+                    return Err(ErrorKind::UnparseableFieldError(
+                        trade.trade_id,
+                        field.to_string(),
+                        value.clone(),
+                    )
+                    .into());
+                }
+            }
+            trade.try_price()?;
+            if i == 0 {
+                continue;
+            }
+            // data is stored from most recent to least recent
+            let newer = &self.data[i - 1];
+            if trade.trade_id >= newer.trade_id {
+                return Err(ErrorKind::NonMonotonicTradeIdError(trade.trade_id).into());
+            }
+            if trade.time_milliseconds > newer.time_milliseconds {
+                return Err(ErrorKind::NonMonotonicTimeError(trade.trade_id).into());
+            }
+        }
         Ok(())
     }
     pub fn save<P: AsRef<Path>>(&self, filename: &P) -> Result<()> {
-        let file = File::create(filename)?;
-        serde_json::to_writer(BufWriter::new(file), &self.data)?;
+        let mut writer = DbWriter::create(filename)?;
+        serde_json::to_writer(&mut writer, &self.data)?;
+        writer.finish()
+    }
+    // indented json for small files meant to be read by hand; same content as save
+    pub fn save_pretty<P: AsRef<Path>>(&self, filename: &P) -> Result<()> {
+        let mut writer = DbWriter::create(filename)?;
+        serde_json::to_writer_pretty(&mut writer, &self.data)?;
+        writer.finish()
+    }
+    // Typed columns for pandas, polars or duckdb, oldest trade first, named like
+    // the json fields: id and time as int64, price, qty and quoteQty as float64,
+    // the maker and best match flags as bool. Not readable back into a Db.
+    pub fn save_parquet<P: AsRef<Path>>(&self, filename: &P) -> Result<()> {
+        let trades: Vec<&HistoricalTrade> = self.iter_chronological().collect();
+        let floats = |field: fn(&HistoricalTrade) -> &String| -> Result<ArrayRef> {
+            let values = trades
+                .iter()
+                .map(|trade| field(trade).parse::<f64>())
+                .collect::<std::result::Result<Vec<f64>, _>>()?;
+            Ok(Arc::new(Float64Array::from(values)))
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(
+                trades.iter().map(|t| t.trade_id).collect::<Vec<i64>>(),
+            )),
+            floats(|t| &t.price)?,
+            floats(|t| &t.quantity)?,
+            floats(|t| &t.quote_quantity)?,
+            Arc::new(Int64Array::from(
+                trades
+                    .iter()
+                    .map(|t| t.time_milliseconds)
+                    .collect::<Vec<i64>>(),
+            )),
+            Arc::new(BooleanArray::from(
+                trades
+                    .iter()
+                    .map(|t| t.is_buyer_maker)
+                    .collect::<Vec<bool>>(),
+            )),
+            Arc::new(BooleanArray::from(
+                trades
+                    .iter()
+                    .map(|t| t.is_best_match)
+                    .collect::<Vec<bool>>(),
+            )),
+        ];
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("qty", DataType::Float64, false),
+            Field::new("quoteQty", DataType::Float64, false),
+            Field::new("time", DataType::Int64, false),
+            Field::new("isBuyerMaker", DataType::Boolean, false),
+            Field::new("isBestMatch", DataType::Boolean, false),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        let mut writer = ArrowWriter::try_new(File::create(filename)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
         Ok(())
     }
+    pub fn new_ndjson<P: AsRef<Path>>(filename: &P) -> Result<Db> {
+        let reader = open_reader(filename)?;
+        let mut deserialized: Vec<HistoricalTrade> = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            deserialized.push(serde_json::from_str(&line)?);
+        }
+        if deserialized.is_empty() {
+            return Err(ErrorKind::EmptyDbError.into());
+        }
+        sort_newest_first(&mut deserialized);
+        Ok(Db { data: deserialized })
+    }
+    // one compact json object per line, so a partially written file is still readable
+    pub fn save_ndjson<P: AsRef<Path>>(&self, filename: &P) -> Result<()> {
+        let flush_every = 10000;
+        let mut writer = DbWriter::create(filename)?;
+        for (i, trade) in self.data.iter().enumerate() {
+            serde_json::to_writer(&mut writer, trade)?;
+            writer.write_all(b"\n")?;
+            if (i + 1) % flush_every == 0 {
+                writer.flush()?;
+            }
+        }
+        writer.finish()
+    }
+}
+
+// Same results as Db::index_range_for_time, but remembers the previous window.
+// While window bounds only move forward, indices are advanced by walking from
+// the previous ones, which is amortized O(1) per query; a bound moving back
+// falls back to binary search.
+pub struct WindowCursor<'a> {
+    db: &'a Db,
+    start_ms: i64,
+    end_ms: i64,
+    start_id: usize,
+    finish_id: usize,
+}
+
+impl<'a> WindowCursor<'a> {
+    pub fn index_range_for_time(&mut self, start_ms: i64, end_ms: i64) -> Result<(usize, usize)> {
+        self.start_id = self.advance(self.start_id, self.start_ms, start_ms);
+        self.finish_id = self.advance(self.finish_id, self.end_ms, end_ms);
+        self.start_ms = start_ms;
+        self.end_ms = end_ms;
+        if start_ms >= end_ms || self.start_id >= self.finish_id {
+            return Err(ErrorKind::InvalidTimeRangeError(start_ms, end_ms).into());
+        }
+        Ok((self.start_id, self.finish_id))
+    }
+    // first_index_at_or_after(ms), given that idx is first_index_at_or_after(prev_ms)
+    fn advance(&self, mut idx: usize, prev_ms: i64, ms: i64) -> usize {
+        if ms < prev_ms {
+            return self.db.first_index_at_or_after(ms);
+        }
+        while idx < self.db.get_data_len() && self.db.get_data(idx).time_milliseconds < ms {
+            idx += 1;
+        }
+        idx
+    }
+}
+
+pub fn parse_agg_trades(data: &str) -> Result<Vec<HistoricalTrade>> {
+    let agg_trades: Vec<AggTrade> = parse_response(data)?;
+    agg_trades
+        .into_iter()
+        .map(HistoricalTrade::try_from)
+        .collect()
+}
+
+// walks several dbs in chronological order, yielding for every trade the time
+// and the most recent trade of each db so far (None before a db's first trade)
+fn forward_fill_by_time<'a>(dbs: &[&'a Db]) -> Vec<(i64, Vec<Option<&'a HistoricalTrade>>)> {
+    let mut positions = vec![0; dbs.len()];
+    let mut latest: Vec<Option<&HistoricalTrade>> = vec![None; dbs.len()];
+    let mut result = Vec::new();
+    loop {
+        let mut next: Option<(usize, i64)> = None;
+        for (i, db) in dbs.iter().enumerate() {
+            if positions[i] >= db.get_data_len() {
+                continue;
+            }
+            let time = db.get_data(positions[i]).time_milliseconds;
+            if next.is_none_or(|(_, next_time)| time < next_time) {
+                next = Some((i, time));
+            }
+        }
+        let (i, time) = match next {
+            None => break,
+            Some(next) => next,
+        };
+        latest[i] = Some(dbs[i].get_data(positions[i]));
+        positions[i] += 1;
+        result.push((time, latest.clone()));
+    }
+    result
+}
+
+// Forward-fill join of two dbs on a common time axis: one pair per trade of either
+// db, holding the most recent trade of each. The side that starts later is None
+// until its first trade.
+pub fn align_by_time<'a>(
+    a: &'a Db,
+    b: &'a Db,
+) -> Vec<(Option<&'a HistoricalTrade>, Option<&'a HistoricalTrade>)> {
+    forward_fill_by_time(&[a, b])
+        .into_iter()
+        .map(|(_, latest)| (latest[0], latest[1]))
+        .collect()
+}
+
+// Pearson correlation of the log returns of a and b over the last window steps
+// of their align_by_time join, at every step once window returns are available,
+// as (time, correlation). Trades of a and b at the same time are one step, steps
+// before both have traded don't count, and windows where either price stood
+// still throughout are skipped.
+pub fn rolling_correlation(a: &Db, b: &Db, window: usize) -> Vec<(i64, f64)> {
+    let mut returns: Vec<(i64, f64, f64)> = Vec::new();
+    let mut previous: Option<(f64, f64)> = None;
+    let steps = forward_fill_by_time(&[a, b]);
+    for (i, (time, latest)) in steps.iter().enumerate() {
+        if steps
+            .get(i + 1)
+            .is_some_and(|(next_time, _)| next_time == time)
+        {
+            continue;
+        }
+        let time = *time;
+        let prices = match (latest[0], latest[1]) {
+            (Some(a_trade), Some(b_trade)) => (a_trade.get_price(), b_trade.get_price()),
+            _ => continue,
+        };
+        if let Some((a_previous, b_previous)) = previous {
+            returns.push((
+                time,
+                (prices.0 / a_previous).ln(),
+                (prices.1 / b_previous).ln(),
+            ));
+        }
+        previous = Some(prices);
+    }
+    let mut correlations = Vec::new();
+    if window < 2 {
+        return correlations;
+    }
+    let n = window as f64;
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (i, &(time, ra, rb)) in returns.iter().enumerate() {
+        sum_a += ra;
+        sum_b += rb;
+        sum_aa += ra * ra;
+        sum_bb += rb * rb;
+        sum_ab += ra * rb;
+        if i >= window {
+            let (_, old_a, old_b) = returns[i - window];
+            sum_a -= old_a;
+            sum_b -= old_b;
+            sum_aa -= old_a * old_a;
+            sum_bb -= old_b * old_b;
+            sum_ab -= old_a * old_b;
+        }
+        if i + 1 < window {
+            continue;
+        }
+        let covariance = sum_ab / n - sum_a / n * sum_b / n;
+        let variance_a = sum_aa / n - (sum_a / n).powi(2);
+        let variance_b = sum_bb / n - (sum_b / n).powi(2);
+        if variance_a <= 0.0 || variance_b <= 0.0 {
+            continue;
+        }
+        correlations.push((time, covariance / (variance_a * variance_b).sqrt()));
+    }
+    correlations
+}
+
+pub struct ArbitrageOpportunity {
+    pub time_milliseconds: i64,
+    pub profit: f64, // fraction gained per cycle, after fees
+}
+
+// For pairs A/B, A/C and B/C (e.g. ETHBTC, ETHUSDT, BTCUSDT), compares the direct
+// A/C rate to the implied A/B * B/C rate at every trade and reports moments where
+// going around the triangle in either direction pays for three fees.
+pub fn find_triangular_arbitrage(ab: &Db, ac: &Db, bc: &Db, fee: f64) -> Vec<ArbitrageOpportunity> {
+    let mut opportunities = Vec::new();
+    for (time, latest) in forward_fill_by_time(&[ab, ac, bc]) {
+        let (ab_trade, ac_trade, bc_trade) = match (latest[0], latest[1], latest[2]) {
+            (Some(ab_trade), Some(ac_trade), Some(bc_trade)) => (ab_trade, ac_trade, bc_trade),
+            _ => continue,
+        };
+        let implied = ab_trade.get_price() * bc_trade.get_price();
+        let direct = ac_trade.get_price();
+        let fees = (1.0 - fee).powi(3);
+        // C -> A -> B -> C, or the other way around
+        let profit = f64::max(implied / direct, direct / implied) * fees - 1.0;
+        if profit > 0.0 {
+            opportunities.push(ArbitrageOpportunity {
+                time_milliseconds: time,
+                profit,
+            });
+        }
+    }
+    opportunities
+}
+
+/*
+    {
+        "symbols": [
+            {
+                "symbol": "ETHBTC",
+                "filters": [
+                    {
+                        "filterType": "PRICE_FILTER",
+                        "minPrice": "0.00000100",
+                        "maxPrice": "922327.00000000",
+                        "tickSize": "0.00000100"
+                    },
+                    {
+                        "filterType": "LOT_SIZE",
+                        "minQty": "0.00010000",
+                        "maxQty": "100000.00000000",
+                        "stepSize": "0.00010000"
+                    },
+                    ...
+                ]
+            }
+        ]
+    }
+*/
+#[derive(Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Deserialize)]
+struct SymbolInfo {
+    symbol: String,
+    filters: Vec<SymbolFilter>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "filterType")]
+enum SymbolFilter {
+    #[serde(rename = "PRICE_FILTER")]
+    Price {
+        #[serde(rename = "minPrice")]
+        min_price: String,
+        #[serde(rename = "maxPrice")]
+        max_price: String,
+        #[serde(rename = "tickSize")]
+        tick_size: String,
+    },
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        #[serde(rename = "minQty")]
+        min_qty: String,
+        #[serde(rename = "maxQty")]
+        max_qty: String,
+        #[serde(rename = "stepSize")]
+        step_size: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SymbolFilters {
+    pub min_price: f64,
+    pub max_price: f64,
+    pub tick_size: f64,
+    pub min_qty: f64,
+    pub max_qty: f64,
+    pub step_size: f64,
+}
+
+impl SymbolFilters {
+    pub fn round_price(&self, price: f64) -> f64 {
+        if self.tick_size == 0.0 {
+            return price;
+        }
+        (price / self.tick_size).round() * self.tick_size
+    }
+    // rounds down, so a rounded order never exceeds the available balance
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        if self.step_size == 0.0 {
+            return quantity;
+        }
+        (quantity / self.step_size).floor() * self.step_size
+    }
+}
+
+pub fn parse_exchange_info(data: &str, symbol: &str) -> Result<SymbolFilters> {
+    let info: ExchangeInfo = parse_response(data)?;
+    let symbol_info = info
+        .symbols
+        .into_iter()
+        .find(|symbol_info| symbol_info.symbol == symbol)
+        .ok_or_else(|| ErrorKind::MissingSymbolFiltersError(symbol.to_string()))?;
+    let mut price_filter = None;
+    let mut lot_size = None;
+    for filter in symbol_info.filters {
+        match filter {
+            SymbolFilter::Price {
+                min_price,
+                max_price,
+                tick_size,
+            } => price_filter = Some((min_price, max_price, tick_size)),
+            SymbolFilter::LotSize {
+                min_qty,
+                max_qty,
+                step_size,
+            } => lot_size = Some((min_qty, max_qty, step_size)),
+            SymbolFilter::Other => (),
+        }
+    }
+    match (price_filter, lot_size) {
+        (Some((min_price, max_price, tick_size)), Some((min_qty, max_qty, step_size))) => {
+            Ok(SymbolFilters {
+                min_price: min_price.parse()?,
+                max_price: max_price.parse()?,
+                tick_size: tick_size.parse()?,
+                min_qty: min_qty.parse()?,
+                max_qty: max_qty.parse()?,
+                step_size: step_size.parse()?,
+            })
+        }
+        _ => Err(ErrorKind::MissingSymbolFiltersError(symbol.to_string()).into()),
+    }
+}
+
+pub async fn fetch_exchange_info(symbol: &Symbol) -> Result<SymbolFilters> {
+    let query = format!("https://api.binance.com/api/v3/exchangeInfo?symbol={symbol}");
+    let res = reqwest::Client::new().get(&query).send().await?;
+    let status = res.status();
+    let data = res.text().await?;
+    if !status.is_success() {
+        return Err(status_error(status, data, query));
+    }
+    parse_exchange_info(&data, symbol.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // a few trades, newest first, with distinct prices, quantities and flags
+    fn sample() -> Db {
+        let trades = (1..=5)
+            .rev()
+            .map(|id| HistoricalTrade {
+                price: format!("0.0{}", 60 + id),
+                quantity: format!("{}.5", id),
+                is_buyer_maker: id % 2 == 0,
+                ..trade(id, 1_652_614_347_000 + id * 250)
+            })
+            .collect();
+        Db::from(trades).unwrap()
+    }
+
+    // a path in the temp dir that no other test, or test run, uses
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("db_test_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn ndjson_round_trips() {
+        let path = temp_path("round_trip.ndjson");
+        sample().save_ndjson(&path).unwrap();
+        let loaded = Db::new_ndjson(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get_all_data_cloned(), sample().get_all_data_cloned());
+    }
+
+    fn trade(trade_id: i64, time_milliseconds: i64) -> HistoricalTrade {
+        HistoricalTrade {
+            trade_id,
+            price: "0.05".to_string(),
+            quantity: "1.0".to_string(),
+            quote_quantity: "0.05".to_string(),
+            time_milliseconds,
+            is_buyer_maker: false,
+            is_best_match: true,
+        }
+    }
+
+    // validate on trades saved as a file and loaded back, the way verify reads them
+    fn validate_fixture(trades: Vec<HistoricalTrade>) -> Error {
+        let path = temp_path(&format!("fixture_{}.json", trades[0].trade_id));
+        std::fs::write(&path, serde_json::to_string(&trades).unwrap()).unwrap();
+        let db = Db::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        db.validate().unwrap_err()
+    }
+
+    #[test]
+    fn validate_reports_the_first_offending_trade() {
+        assert!(sample().validate().is_ok());
+        // loading sorts by id, so out of order ids surface as a repeated id
+        let duplicate_id = vec![trade(101, 1000), trade(102, 2000), trade(102, 3000)];
+        assert!(matches!(
+            validate_fixture(duplicate_id).kind(),
+            ErrorKind::NonMonotonicTradeIdError(102)
+        ));
+        let time_goes_back = vec![trade(201, 1000), trade(202, 3000), trade(203, 2000)];
+        assert!(matches!(
+            validate_fixture(time_goes_back).kind(),
+            ErrorKind::NonMonotonicTimeError(202)
+        ));
+        let mut garbage_price = vec![trade(301, 1000), trade(302, 2000)];
+        garbage_price[0].price = "0.05x".to_string();
+        assert!(matches!(
+            validate_fixture(garbage_price).kind(),
+            ErrorKind::UnparseableFieldError(301, field, value) if field == "price" && value == "0.05x"
+        ));
+        let mut zero_price = vec![trade(401, 1000)];
+        zero_price[0].price = "0.0".to_string();
+        assert!(matches!(
+            validate_fixture(zero_price).kind(),
+            ErrorKind::NonPositivePriceError(401, _)
+        ));
+    }
+
+    #[test]
+    fn gzip_round_trips_like_plain_json() {
+        let plain = temp_path("round_trip.json");
+        let gzipped = temp_path("round_trip.json.gz");
+        sample().save(&plain).unwrap();
+        sample().save(&gzipped).unwrap();
+        let mut magic = [0; 2];
+        File::open(&gzipped)
+            .unwrap()
+            .read_exact(&mut magic)
+            .unwrap();
+        let from_plain = Db::new(&plain).unwrap().get_all_data_cloned();
+        let from_gzipped = Db::new(&gzipped).unwrap().get_all_data_cloned();
+        let from_mmap = Db::open_mmap(&plain).unwrap().get_all_data_cloned();
+        let from_gzipped_mmap = Db::open_mmap(&gzipped).unwrap().get_all_data_cloned();
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&gzipped).unwrap();
+        assert_eq!(magic, [0x1f, 0x8b]);
+        assert_eq!(from_gzipped, from_plain);
+        assert_eq!(from_mmap, from_plain);
+        assert_eq!(from_gzipped_mmap, from_plain);
+        assert_eq!(from_plain, sample().get_all_data_cloned());
+    }
+
+    // trades at (time, price), in time order, with increasing ids
+    fn series(points: &[(i64, f64)]) -> Db {
+        let trades = points
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, &(time, price))| HistoricalTrade {
+                price: price.to_string(),
+                ..trade(i as i64 + 1, time)
+            })
+            .collect();
+        Db::from(trades).unwrap()
+    }
+
+    #[test]
+    fn triangular_arbitrage_in_one_window() {
+        let eth_btc = series(&[(1000, 0.05), (5000, 0.05)]);
+        // ETHUSDT strays from ETHBTC * BTCUSDT = 2000 between 3000 and 4000
+        let eth_usdt = series(&[(1500, 2000.0), (3000, 2100.0), (4000, 2000.0)]);
+        let btc_usdt = series(&[(2000, 40000.0), (6000, 40000.0)]);
+        let opportunities = find_triangular_arbitrage(&eth_btc, &eth_usdt, &btc_usdt, 0.001);
+        assert_eq!(opportunities.len(), 1);
+        assert_eq!(opportunities[0].time_milliseconds, 3000);
+        let expected = 2100.0 / 2000.0 * 0.999f64.powi(3) - 1.0;
+        assert!((opportunities[0].profit - expected).abs() < 1e-12);
+        // fees above the gap leave nothing
+        assert!(find_triangular_arbitrage(&eth_btc, &eth_usdt, &btc_usdt, 0.02).is_empty());
+    }
+
+    #[test]
+    fn align_by_time_forward_fills_staggered_series() {
+        // b starts later and trades in between a's trades
+        let a = series(&[(1000, 1.0), (3000, 3.0), (5000, 5.0)]);
+        let b = series(&[(2000, 20.0), (4000, 40.0)]);
+        let pairs: Vec<(Option<f64>, Option<f64>)> = align_by_time(&a, &b)
+            .into_iter()
+            .map(|(a, b)| (a.map(|t| t.get_price()), b.map(|t| t.get_price())))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![
+                (Some(1.0), None),
+                (Some(1.0), Some(20.0)),
+                (Some(3.0), Some(20.0)),
+                (Some(3.0), Some(40.0)),
+                (Some(5.0), Some(40.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn pages_to_reach_rounds_up_to_whole_pages() {
+        // sample() holds trades 1 to 5
+        let db = sample();
+        assert_eq!(db.pages_to_reach(1, 1000).unwrap(), 0);
+        assert_eq!(db.pages_to_reach(5, 1000).unwrap(), 0);
+        assert_eq!(db.pages_to_reach(0, 1000).unwrap(), 1);
+        assert_eq!(db.pages_to_reach(-1000, 1000).unwrap(), 2);
+        assert_eq!(db.pages_to_reach(-999, 1000).unwrap(), 1);
+    }
+
+    #[test]
+    fn historical_trades_fixture_parses_the_same_from_api_and_file() {
+        let data = include_str!("../tests/data/ethbtc_historical_trades.json");
+        // the getter decodes api pages with parse_response and reads saved files with Db::new
+        let from_api: Vec<HistoricalTrade> = parse_response(data).unwrap();
+        let path = temp_path("historical_trades.json");
+        std::fs::write(&path, data).unwrap();
+        let from_file = Db::new(&path).unwrap().get_all_data_cloned();
+        std::fs::remove_file(&path).unwrap();
+        let expected = HistoricalTrade {
+            trade_id: 340327051,
+            price: "0.06901500".to_string(),
+            quantity: "0.00160000".to_string(),
+            quote_quantity: "0.00011042".to_string(),
+            time_milliseconds: 1652614347356,
+            is_buyer_maker: false,
+            is_best_match: true,
+        };
+        assert_eq!(from_api[1], expected);
+        // the file is loaded newest first
+        assert_eq!(from_file, from_api.into_iter().rev().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn page_limits_outside_binance_range_are_rejected() {
+        let symbol = Symbol::new("ETHBTC").unwrap();
+        // the limit is checked before any page is fetched
+        let fetcher = InMemoryTradeFetcher::new(vec![trade(1, 1000), trade(2, 2000)]);
+        for limit in [0, -1, MAX_PAGE_LIMIT + 1] {
+            let mut db = Db::from(vec![trade(3, 3000)]).unwrap();
+            let err = db
+                .load_more_data(&fetcher, &symbol, limit)
+                .await
+                .unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::InvalidLimitError(l) if *l == limit));
+            assert_eq!(db.get_data_len(), 1);
+            assert!(db.pages_to_reach(0, limit).is_err());
+        }
+        let mut db = Db::from(vec![trade(3, 3000)]).unwrap();
+        assert_eq!(db.pages_to_reach(0, 2).unwrap(), 2);
+        db.load_more_data(&fetcher, &symbol, 2).await.unwrap();
+        assert_eq!(db.get_min_trade_id(), 1);
+    }
+
+    #[test]
+    fn exchange_info_fixture_parses() {
+        let data = include_str!("../tests/data/ethbtc_exchange_info.json");
+        let filters = parse_exchange_info(data, "ETHBTC").unwrap();
+        assert_eq!(
+            filters,
+            SymbolFilters {
+                min_price: 0.000001,
+                max_price: 922327.0,
+                tick_size: 0.000001,
+                min_qty: 0.0001,
+                max_qty: 100000.0,
+                step_size: 0.0001,
+            }
+        );
+        assert!((filters.round_price(0.0690154) - 0.069015).abs() < 1e-12);
+        assert!((filters.round_quantity(0.00169) - 0.0016).abs() < 1e-12);
+        assert!(matches!(
+            parse_exchange_info(data, "BNBBTC").unwrap_err().kind(),
+            ErrorKind::MissingSymbolFiltersError(symbol) if symbol == "BNBBTC"
+        ));
+    }
+
+    #[test]
+    fn iter_chronological_starts_at_the_oldest_trade() {
+        let db = sample();
+        assert_eq!(db.iter_chronological().next().unwrap().trade_id, 1);
+        assert_eq!(db.iter_recent_first().next().unwrap().trade_id, 5);
+        let chronological: Vec<i64> = db.iter_chronological().map(|t| t.trade_id).collect();
+        let by_index: Vec<i64> = (0..db.get_data_len())
+            .map(|idx| db.get_data(idx).trade_id)
+            .collect();
+        assert_eq!(chronological, by_index);
+    }
+
+    #[test]
+    fn sign_query_matches_known_vectors() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_query("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // the example from binance's api docs for signed endpoints
+        let secret = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+        let query = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+        assert_eq!(
+            sign_query(secret, query),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+    }
+
+    #[test]
+    fn binance_error_bodies_are_typed_errors() {
+        let data = r#"{"code":-1121,"msg":"Invalid symbol."}"#;
+        let err = parse_response::<Vec<HistoricalTrade>>(data).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::BinanceApiError(-1121, msg) if msg == "Invalid symbol."
+        ));
+        // anything else that doesn't decode is still a decode error
+        let err = parse_response::<Vec<HistoricalTrade>>(r#"{"unexpected":true}"#).unwrap_err();
+        assert!(!matches!(err.kind(), ErrorKind::BinanceApiError(..)));
+    }
+
+    #[test]
+    fn downsample_keeps_both_endpoints() {
+        let ids = |db: Db| {
+            db.iter_chronological()
+                .map(|t| t.trade_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(sample().downsample(2).unwrap()), vec![1, 3, 5]);
+        // the most recent trade is kept even off the stride
+        assert_eq!(ids(sample().downsample(3).unwrap()), vec![1, 4, 5]);
+        assert_eq!(ids(sample().downsample(10).unwrap()), vec![1, 5]);
+        assert_eq!(sample().downsample(1).unwrap().get_data_len(), 5);
+        assert!(sample().downsample(0).is_err());
+    }
+
+    #[test]
+    fn milliseconds_round_trip_through_utc_strings() {
+        let milliseconds = 1652614347356;
+        let formatted = format_milliseconds(milliseconds);
+        assert_eq!(formatted, "2022-05-15T11:32:27.356Z");
+        assert_eq!(parse_datetime(&formatted).unwrap(), milliseconds);
+        assert_eq!(
+            trade(1, milliseconds).trade_datetime().timestamp_millis(),
+            milliseconds
+        );
+        // offsets and the naive forms, taken as UTC, land on the same instant
+        assert_eq!(
+            parse_datetime("2022-05-15T13:32:27.356+02:00").unwrap(),
+            milliseconds
+        );
+        assert_eq!(
+            parse_datetime("2022-05-15 11:32:27.356").unwrap(),
+            milliseconds
+        );
+        assert_eq!(parse_datetime("2022-05-15").unwrap(), 1652572800000);
+        assert_eq!(parse_time("1652614347356").unwrap(), milliseconds);
+        assert!(parse_datetime("15/05/2022").is_err());
+    }
+
+    #[test]
+    fn window_cursor_matches_fresh_searches() {
+        let db = series(&[
+            (1000, 1.0),
+            (1000, 1.0),
+            (2500, 1.0),
+            (4000, 1.0),
+            (4001, 1.0),
+            (7000, 1.0),
+        ]);
+        let mut cursor = db.window_cursor();
+        // sliding forward, a repeated window, a jump back and windows outside the data
+        let mut windows: Vec<(i64, i64)> = (0..8).map(|i| (i * 1000, i * 1000 + 2000)).collect();
+        windows.extend([
+            (6000, 8000),
+            (1000, 4001),
+            (0, 500),
+            (8000, 9000),
+            (3000, 3000),
+        ]);
+        for (start_ms, end_ms) in windows {
+            let fresh = db.index_range_for_time(start_ms, end_ms).ok();
+            let cached = cursor.index_range_for_time(start_ms, end_ms).ok();
+            assert_eq!(cached, fresh, "{start_ms}..{end_ms}");
+        }
+    }
+
+    #[test]
+    fn throttles_once_the_used_weight_header_is_high() {
+        let headers = |weight: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert("x-mbx-used-weight-1m", weight.parse().unwrap());
+            headers
+        };
+        assert_eq!(used_weight(&headers("1000")), Some(1000));
+        assert_eq!(used_weight(&headers("lots")), None);
+        assert_eq!(used_weight(&reqwest::header::HeaderMap::new()), None);
+        // 25 seconds into a minute, the next window is 35 seconds away
+        let now_ms = 1_652_614_345_000;
+        assert_eq!(throttle_delay_milliseconds(1000, 960, now_ms), Some(35_000));
+        assert_eq!(throttle_delay_milliseconds(960, 960, now_ms), Some(35_000));
+        assert_eq!(throttle_delay_milliseconds(959, 960, now_ms), None);
+    }
+
+    #[test]
+    fn splits_share_no_trades_and_respect_the_boundary() {
+        let db = sample();
+        let boundary_ms = db.get_data(2).time_milliseconds;
+        let (earlier, later) = db.split_at_time(boundary_ms).unwrap();
+        assert!(earlier
+            .iter_recent_first()
+            .all(|t| t.time_milliseconds < boundary_ms));
+        assert!(later
+            .iter_recent_first()
+            .all(|t| t.time_milliseconds >= boundary_ms));
+        assert_eq!(earlier.get_max_trade_id(), 2);
+        assert_eq!(later.get_min_trade_id(), 3);
+        assert_eq!(
+            earlier.get_data_len() + later.get_data_len(),
+            db.get_data_len()
+        );
+        let (earlier, later) = db.split_at_fraction(0.4).unwrap();
+        assert_eq!((earlier.get_data_len(), later.get_data_len()), (2, 3));
+        // a boundary past all trades would leave the later db empty
+        assert!(db.split_at_time(boundary_ms + 10_000).is_err());
+    }
+
+    #[tokio::test]
+    async fn load_more_data_pages_offline() {
+        let symbol = Symbol::new("ETHBTC").unwrap();
+        let recent = || Db::from(vec![trade(10, 10_000), trade(9, 9000)]).unwrap();
+        // a normal page goes below the oldest trade
+        let fetcher = InMemoryTradeFetcher::new((1..=10).map(|id| trade(id, id * 1000)).collect());
+        let mut db = recent();
+        db.load_more_data(&fetcher, &symbol, 3).await.unwrap();
+        let ids: Vec<i64> = db.iter_chronological().map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![6, 7, 8, 9, 10]);
+        // nothing older to fetch
+        let mut db = recent();
+        let err = db
+            .load_more_data(&InMemoryTradeFetcher::new(Vec::new()), &symbol, 3)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::EmptyDbError));
+        // a page reaching into trades already held
+        let fetcher = InMemoryTradeFetcher::new(vec![trade(9, 9000), trade(10, 10_000)]);
+        let mut db = recent();
+        let err = db.load_more_data(&fetcher, &symbol, 3).await.unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::IntersectingTradeSlicesError(9, 9)
+        ));
+        assert_eq!(db.get_data_len(), 2);
+    }
+
+    #[test]
+    fn merging_overlapping_dbs_keeps_each_trade_once() {
+        let a = Db::from(vec![trade(4, 4000), trade(3, 3000), trade(2, 2000)]).unwrap();
+        let b = Db::from(vec![
+            trade(6, 6000),
+            trade(5, 5000),
+            trade(4, 4000),
+            trade(3, 3000),
+        ])
+        .unwrap();
+        let merged = Db::merge(a, b).unwrap();
+        let ids: Vec<i64> = merged.iter_recent_first().map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![6, 5, 4, 3, 2]);
+        assert!(merged.validate().is_ok());
+    }
+
+    #[test]
+    fn volume_profile_sums_quantities_per_price_bucket() {
+        // newest first, as Db::from expects
+        let trades = [
+            (5, "2.5", "0.5"),
+            (4, "4", "4"),
+            (3, "3", "3"),
+            (2, "2", "2"),
+            (1, "1", "1.5"),
+        ]
+        .iter()
+        .map(|&(trade_id, price, quantity)| HistoricalTrade {
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+            ..trade(trade_id, trade_id * 1000)
+        })
+        .collect();
+        let db = Db::from(trades).unwrap();
+        // buckets [1, 2), [2, 3) and [3, 4], the max price in the last one
+        assert_eq!(
+            db.volume_profile(3),
+            vec![(1.0, 1.5), (2.0, 2.5), (3.0, 7.0)]
+        );
+        assert!(db.volume_profile(0).is_empty());
+        // a single price level puts everything in the first bucket
+        let flat = Db::from(vec![trade(2, 2000), trade(1, 1000)]).unwrap();
+        assert_eq!(flat.volume_profile(2), vec![(0.05, 2.0), (0.05, 0.0)]);
+    }
+
+    #[test]
+    fn page_estimate_follows_the_observed_trade_rate() {
+        // one trade every 4 ms, newest first
+        let trades = (1..=11)
+            .rev()
+            .map(|id| trade(id, 1_000_000 + id * 4))
+            .collect();
+        let db = Db::from(trades).unwrap();
+        let oldest_ms = db.get_min_time_milliseconds();
+        // 4000 ms hold 1000 trades, one page
+        assert_eq!(db.estimate_pages_for_time(oldest_ms - 4000), 1);
+        assert_eq!(db.estimate_pages_for_time(oldest_ms - 4004), 2);
+        assert_eq!(db.estimate_pages_for_time(oldest_ms - 400_000), 100);
+        assert_eq!(db.estimate_pages_for_time(oldest_ms), 0);
+        assert_eq!(db.estimate_pages_for_time(oldest_ms + 10), 0);
+    }
+
+    #[test]
+    fn trimming_keeps_the_most_recent_trades() {
+        let mut db = sample();
+        db.trim_to_recent(2).unwrap();
+        let ids: Vec<i64> = db.iter_recent_first().map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![5, 4]);
+        // keeping more than there are is a no-op, keeping none is an error
+        db.trim_to_recent(10).unwrap();
+        assert_eq!(db.get_data_len(), 2);
+        assert!(db.trim_to_recent(0).is_err());
+        assert_eq!(db.get_data_len(), 2);
+    }
+
+    #[test]
+    fn resample_carries_the_last_price_forward() {
+        let db = series(&[(1200, 1.0), (1900, 2.0), (4100, 3.0), (5000, 4.0)]);
+        // no trades between 2000 and 4000, and a trade exactly on 5000
+        assert_eq!(
+            db.resample(1000),
+            vec![(2000, 2.0), (3000, 2.0), (4000, 2.0), (5000, 4.0)]
+        );
+        assert_eq!(db.resample(2500), vec![(2500, 2.0), (5000, 4.0)]);
+        assert!(db.resample(0).is_empty());
+    }
+
+    #[test]
+    fn symbols_are_validated() {
+        for symbol in ["ETHBTC", "BTCUSDT", "1INCHBTC", "AB"] {
+            assert_eq!(Symbol::new(symbol).unwrap().as_str(), symbol);
+        }
+        let too_long = "A".repeat(21);
+        for symbol in [
+            "",
+            "E",
+            "ethbtc",
+            "ETH-BTC",
+            "ETHBTC&limit=1",
+            "ETH BTC",
+            too_long.as_str(),
+        ] {
+            assert!(
+                matches!(Symbol::new(symbol).unwrap_err().kind(), ErrorKind::InvalidSymbolError(s) if s == symbol),
+                "{symbol}"
+            );
+        }
+        assert_eq!("BNBETH".parse::<Symbol>().unwrap().to_string(), "BNBETH");
+    }
+
+    #[test]
+    fn time_chunks_cover_the_range_without_gaps() {
+        let hour = MAX_AGG_TRADES_WINDOW_MS;
+        assert_eq!(
+            time_chunks(0, 2 * hour + 5, hour),
+            vec![(0, hour), (hour, 2 * hour), (2 * hour, 2 * hour + 5)]
+        );
+        assert_eq!(time_chunks(0, hour, hour), vec![(0, hour)]);
+        assert!(time_chunks(5, 5, hour).is_empty());
+    }
+
+    #[test]
+    fn price_at_time_takes_the_last_trade_at_or_before() {
+        let db = series(&[(1000, 1.0), (2000, 2.0), (2000, 2.5), (3000, 3.0)]);
+        assert_eq!(db.price_at_time(999), None);
+        assert_eq!(db.price_at_time(1000), Some(1.0));
+        assert_eq!(db.price_at_time(1500), Some(1.0));
+        // the later of two trades in the same millisecond
+        assert_eq!(db.price_at_time(2000), Some(2.5));
+        assert_eq!(db.price_at_time(3000), Some(3.0));
+        assert_eq!(db.price_at_time(i64::MAX), Some(3.0));
+    }
+
+    #[test]
+    fn pretty_output_parses_back_to_an_equal_db() {
+        let path = temp_path("pretty.json");
+        sample().save_pretty(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let loaded = Db::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(text.contains("\n  {\n    \"id\": 5,"));
+        assert_eq!(loaded.get_all_data_cloned(), sample().get_all_data_cloned());
+    }
+
+    // answers with the given status codes in turn, then with pages
+    struct FailingFetcher {
+        statuses: Mutex<VecDeque<u16>>,
+        calls: AtomicUsize,
+        pages: InMemoryTradeFetcher,
+    }
+
+    impl TradeFetcher for FailingFetcher {
+        async fn fetch(
+            &self,
+            symbol: &Symbol,
+            from_id: i64,
+            limit: i64,
+        ) -> Result<Vec<HistoricalTrade>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let status = self.statuses.lock().unwrap().pop_front();
+            if let Some(status) = status {
+                let status = reqwest::StatusCode::from_u16(status).unwrap();
+                return Err(status_error(status, "{}".to_string(), "query".to_string()));
+            }
+            self.pages.fetch(symbol, from_id, limit).await
+        }
+    }
+
+    fn failing_fetcher(statuses: &[u16]) -> FailingFetcher {
+        FailingFetcher {
+            statuses: Mutex::new(statuses.iter().copied().collect()),
+            calls: AtomicUsize::new(0),
+            pages: InMemoryTradeFetcher::new((1..=10).map(|id| trade(id, id * 1000)).collect()),
+        }
+    }
+
+    // paused, so the backoff sleeps pass instantly
+    #[tokio::test(start_paused = true)]
+    async fn server_errors_are_retried_and_client_errors_are_not() {
+        let symbol = Symbol::new("ETHBTC").unwrap();
+        let recent = || Db::from(vec![trade(10, 10_000), trade(9, 9000)]).unwrap();
+        // a 500 and a 503, then the page
+        let fetcher = failing_fetcher(&[500, 503]);
+        let mut db = recent();
+        db.load_more_data(&fetcher, &symbol, 3).await.unwrap();
+        assert_eq!(fetcher.calls.load(Ordering::SeqCst), 3);
+        assert_eq!(db.get_min_trade_id(), 6);
+        // a 400 fails on the spot, body included
+        let fetcher = failing_fetcher(&[400]);
+        let err = recent()
+            .load_more_data(&fetcher, &symbol, 3)
+            .await
+            .unwrap_err();
+        assert_eq!(fetcher.calls.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::ClientError(status, body, _) if status.as_u16() == 400 && body == "{}"
+        ));
+        // 5xx that keeps coming gives up after the retries
+        let statuses = vec![500; MAX_SERVER_ERROR_RETRIES as usize + 1];
+        let fetcher = failing_fetcher(&statuses);
+        let err = recent()
+            .load_more_data(&fetcher, &symbol, 3)
+            .await
+            .unwrap_err();
+        assert_eq!(fetcher.calls.load(Ordering::SeqCst), statuses.len());
+        assert!(matches!(err.kind(), ErrorKind::ServerError(..)));
+    }
+
+    #[test]
+    fn from_trades_unsorted_sorts_shuffled_input() {
+        let shuffled = [3, 1, 5, 2, 4]
+            .iter()
+            .map(|&id| trade(id, 1_000 + id))
+            .collect();
+        let db = Db::from_trades_unsorted(shuffled).unwrap();
+        assert_eq!(db.get_data_len(), 5);
+        assert_eq!(db.get_max_trade_id(), 5);
+        assert_eq!(db.get_min_trade_id(), 1);
+        assert_eq!(db.get_max_time_milliseconds(), 1_005);
+        assert_eq!(db.get_min_time_milliseconds(), 1_001);
+        let ids: Vec<i64> = db.iter_recent_first().map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![5, 4, 3, 2, 1]);
+        assert_eq!(db.get_data(0).trade_id, 1);
+    }
+
+    #[test]
+    fn quantity_stats_mean_and_median() {
+        // quantities 1.5, 2.5, 3.5, 4.5, 5.5
+        let stats = sample().quantity_stats();
+        assert!((stats.mean - 3.5).abs() < 1e-12);
+        assert!((stats.median - 3.5).abs() < 1e-12);
+        assert_eq!(stats.min, 1.5);
+        assert_eq!(stats.max, 5.5);
+        // rank 0.25 * 4 = 1 lands exactly on the second quantity
+        assert!((stats.p25 - 2.5).abs() < 1e-12);
+
+        // an even count takes the midpoint of the middle two
+        let mut db = sample();
+        db.trim_to_recent(4).unwrap();
+        let stats = db.quantity_stats();
+        assert!((stats.mean - 4.0).abs() < 1e-12);
+        assert!((stats.median - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bad_prices_are_errors_not_panics() {
+        for price in ["0", "-0.05", "NaN", "inf", "-inf", "garbage"] {
+            let mut bad = trade(2, 2000);
+            bad.price = price.to_string();
+            assert!(bad.try_price().is_err(), "{price}");
+            let db = || Db::from(vec![bad.clone(), trade(1, 1000)]).unwrap();
+            assert!(db().validate().is_err(), "{price}");
+            assert_eq!(db().check_prices(BadPricePolicy::Skip).unwrap(), 1);
+            assert!(db().check_prices(BadPricePolicy::Reject).is_err());
+        }
+        assert_eq!(trade(1, 1000).try_price().unwrap(), 0.05);
+    }
+
+    #[test]
+    fn parquet_round_trips_through_arrow() {
+        use arrow::array::Array;
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let db = sample();
+        let path = temp_path("parquet_round_trip.parquet");
+        db.save_parquet(&path).unwrap();
+        let batches = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<std::result::Result<Vec<RecordBatch>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), db.get_data_len());
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let ids = column("id");
+        let ids = ids.as_any().downcast_ref::<Int64Array>().unwrap();
+        let prices = column("price");
+        let prices = prices.as_any().downcast_ref::<Float64Array>().unwrap();
+        let quantities = column("qty");
+        let quantities = quantities.as_any().downcast_ref::<Float64Array>().unwrap();
+        let quote_quantities = column("quoteQty");
+        let quote_quantities = quote_quantities
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        let times = column("time");
+        let times = times.as_any().downcast_ref::<Int64Array>().unwrap();
+        let makers = column("isBuyerMaker");
+        let makers = makers.as_any().downcast_ref::<BooleanArray>().unwrap();
+        let best_matches = column("isBestMatch");
+        let best_matches = best_matches
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        // oldest first, like get_data
+        for (row, trade) in db.iter_chronological().enumerate() {
+            assert_eq!(ids.value(row), trade.trade_id);
+            assert_eq!(prices.value(row), trade.get_price());
+            assert_eq!(quantities.value(row), trade.get_quantity());
+            assert_eq!(
+                quote_quantities.value(row),
+                trade.quote_quantity.parse::<f64>().unwrap()
+            );
+            assert_eq!(times.value(row), trade.time_milliseconds);
+            assert_eq!(makers.value(row), trade.is_buyer_maker);
+            assert_eq!(best_matches.value(row), trade.is_best_match);
+        }
+        assert_eq!(ids.null_count(), 0);
+    }
+
+    #[test]
+    fn rolling_correlation_of_partly_shared_returns() {
+        // b's log returns are a's plus a pattern uncorrelated with them over any
+        // 4 steps, of the same size, so the correlation is 1/sqrt(2) throughout
+        let a_returns = [0.01, -0.01, 0.01, -0.01, 0.01, -0.01, 0.01, -0.01, 0.01];
+        let noise = [0.01, 0.01, -0.01, -0.01, 0.01, 0.01, -0.01, -0.01, 0.01];
+        let prices = |returns: &[f64]| -> Vec<(i64, f64)> {
+            let mut price = 1.0;
+            let mut points = vec![(1000, price)];
+            for (i, r) in returns.iter().enumerate() {
+                price *= f64::exp(*r);
+                points.push((2000 + i as i64 * 1000, price));
+            }
+            points
+        };
+        let b_returns: Vec<f64> = a_returns.iter().zip(noise).map(|(a, n)| a + n).collect();
+        let a = series(&prices(&a_returns));
+        let b = series(&prices(&b_returns));
+        let correlations = rolling_correlation(&a, &b, 4);
+        // the first window ends with the 4th return
+        assert_eq!(correlations.len(), a_returns.len() - 3);
+        assert_eq!(correlations[0].0, 5000);
+        for (_, correlation) in &correlations {
+            assert!((correlation - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        }
+        // a series against itself
+        let itself = rolling_correlation(&a, &a, 4);
+        assert!(itself.iter().all(|(_, c)| (c - 1.0).abs() < 1e-6));
+        assert!(rolling_correlation(&a, &b, 20).is_empty());
+    }
+
+    #[test]
+    fn file_summaries_match_the_loaded_db() {
+        let assert_matches = |path: &Path| {
+            let summary = summarize_file(&path).unwrap();
+            let db = Db::new(&path).unwrap();
+            assert_eq!(summary.count, db.get_data_len());
+            assert_eq!(summary.min_trade_id, db.get_min_trade_id());
+            assert_eq!(summary.max_trade_id, db.get_max_trade_id());
+            assert_eq!(
+                summary.min_time_milliseconds,
+                db.get_min_time_milliseconds()
+            );
+            assert_eq!(
+                summary.max_time_milliseconds,
+                db.get_max_time_milliseconds()
+            );
+        };
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/ethbtc_historical_trades.json");
+        assert_matches(&fixture);
+        assert_eq!(summarize_file(&fixture).unwrap().count, 2);
+
+        let ndjson = temp_path("summary.ndjson");
+        sample().save_ndjson(&ndjson).unwrap();
+        let summary = summarize_file(&ndjson).unwrap();
+        assert_eq!((summary.min_trade_id, summary.max_trade_id), (1, 5));
+        assert_eq!(
+            summary.count,
+            Db::new_ndjson(&ndjson).unwrap().get_data_len()
+        );
+        std::fs::remove_file(&ndjson).unwrap();
+
+        let gzipped = temp_path("summary.json.gz");
+        sample().save(&gzipped).unwrap();
+        assert_matches(&gzipped);
+        std::fs::remove_file(&gzipped).unwrap();
+    }
+
+    #[test]
+    fn trades_per_interval_counts_clustered_trades() {
+        // a burst, a quiet second, a pair, another quiet second and a straggler
+        let db = series(&[
+            (10_100, 1.0),
+            (10_200, 1.0),
+            (10_300, 1.0),
+            (10_999, 1.0),
+            (12_000, 1.0),
+            (12_900, 1.0),
+            (14_500, 1.0),
+        ]);
+        assert_eq!(
+            db.trades_per_interval(1000),
+            vec![
+                (10_000, 4),
+                (11_000, 0),
+                (12_000, 2),
+                (13_000, 0),
+                (14_000, 1)
+            ]
+        );
+        let total: usize = db
+            .trades_per_interval(300)
+            .iter()
+            .map(|(_, count)| count)
+            .sum();
+        assert_eq!(total, db.get_data_len());
+        assert!(db.trades_per_interval(0).is_empty());
+    }
+
+    #[test]
+    fn storage_is_newest_first_and_positions_are_chronological() {
+        // written oldest first, the order the api pages come in
+        let path = temp_path("ordering.json");
+        let oldest_first: Vec<HistoricalTrade> = (1..=4).map(|id| trade(id, 1_000 * id)).collect();
+        std::fs::write(&path, serde_json::to_string(&oldest_first).unwrap()).unwrap();
+        let db = Db::new(&path).unwrap();
+        // stored, cloned and saved newest first
+        let ids =
+            |trades: &[HistoricalTrade]| trades.iter().map(|t| t.trade_id).collect::<Vec<_>>();
+        assert_eq!(ids(&db.get_all_data_cloned()), vec![4, 3, 2, 1]);
+        db.save(&path).unwrap();
+        let saved: Vec<HistoricalTrade> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ids(&saved), vec![4, 3, 2, 1]);
+        // positions count from the oldest trade
+        assert_eq!(db.get_data(0).trade_id, 1);
+        assert_eq!(db.get_data(3).trade_id, 4);
+        assert_eq!(ids(db.get_window(1, 3)), vec![3, 2]);
+        assert_eq!(db.iter_chronological().next().unwrap().trade_id, 1);
+        // the min and max getters don't depend on either
+        assert_eq!((db.get_min_trade_id(), db.get_max_trade_id()), (1, 4));
+        assert_eq!(
+            (
+                db.get_min_time_milliseconds(),
+                db.get_max_time_milliseconds()
+            ),
+            (1_000, 4_000)
+        );
+        // Db::from takes only the stored order
+        assert!(matches!(
+            Db::from(oldest_first).err().unwrap().kind(),
+            ErrorKind::NonMonotonicTradeIdError(2)
+        ));
+    }
+
+    #[test]
+    fn order_flow_imbalance_follows_the_aggressor_side() {
+        // three aggressive buys, then aggressive sells take over
+        let flows = [
+            (false, "1.0"),
+            (false, "2.0"),
+            (false, "1.0"),
+            (true, "2.0"),
+            (true, "2.0"),
+        ];
+        let trades = flows
+            .iter()
+            .enumerate()
+            .map(|(i, &(is_buyer_maker, quantity))| HistoricalTrade {
+                quantity: quantity.to_string(),
+                is_buyer_maker,
+                ..trade(i as i64 + 1, 1_000 * (i as i64 + 1))
+            })
+            .collect();
+        let db = Db::from_trades_unsorted(trades).unwrap();
+        let imbalances = db.order_flow_imbalance(3);
+        // (4 - 0) / 4, (3 - 2) / 5, (1 - 4) / 5
+        let expected = [1.0, 0.2, -0.6];
+        assert_eq!(imbalances.len(), expected.len());
+        for (imbalance, expected) in imbalances.iter().zip(expected) {
+            assert!((imbalance - expected).abs() < 1e-12);
+        }
+        // a window of one is the sign of each trade
+        assert_eq!(db.order_flow_imbalance(1), vec![1.0, 1.0, 1.0, -1.0, -1.0]);
+        assert!(db.order_flow_imbalance(0).is_empty());
+        assert!(db.order_flow_imbalance(6).is_empty());
+    }
+
+    #[test]
+    fn full_pages_in_one_millisecond_continue_by_id() {
+        // a whole page sharing a millisecond: the next page starts after its last id
+        let mut page = vec![trade(10, 500), trade(11, 500), trade(12, 500)];
+        assert_eq!(clip_page(&mut page, 1000, 3), Some(13));
+        assert_eq!(page.len(), 3);
+        // a page that isn't full is the last one
+        let mut page = vec![trade(13, 500), trade(14, 501)];
+        assert_eq!(clip_page(&mut page, 1000, 3), None);
+        assert_eq!(page.len(), 2);
+        // so is one that reaches end_ms, whose later trades belong to the next chunk
+        let mut page = vec![trade(13, 999), trade(14, 1000), trade(15, 1001)];
+        assert_eq!(clip_page(&mut page, 1000, 3), None);
+        assert_eq!(page, vec![trade(13, 999)]);
+    }
+
+    #[test]
+    fn candles_of_a_small_series() {
+        let prices = [
+            (1, 1000, "3"),
+            (2, 1500, "5"),
+            (3, 1999, "4"),
+            (4, 4200, "2"),
+        ];
+        let trades = prices
+            .iter()
+            .map(|&(trade_id, time, price)| HistoricalTrade {
+                price: price.to_string(),
+                ..trade(trade_id, time)
+            })
+            .collect();
+        let db = Db::from_trades_unsorted(trades).unwrap();
+        let candles = db.to_candles(1000);
+        // the quiet buckets at 2000 and 3000 are skipped
+        assert_eq!(candles.len(), 2);
+        let first = &candles[0];
+        assert_eq!(first.open_time_milliseconds, 1000);
+        assert_eq!(
+            (first.open, first.high, first.low, first.close),
+            (3.0, 5.0, 3.0, 4.0)
+        );
+        assert_eq!((first.volume, first.trade_count), (3.0, 3));
+        assert_eq!(candles[1].open_time_milliseconds, 4000);
+        assert_eq!(candles[1].close, 2.0);
+        assert!(db.to_candles(0).is_empty());
+        assert!(db.to_candles(-1000).is_empty());
+        // times before the epoch still start their bucket at or before them
+        let db = Db::from(vec![trade(1, -1)]).unwrap();
+        assert_eq!(db.to_candles(1000)[0].open_time_milliseconds, -1000);
+    }
+
+    #[test]
+    fn agg_trades_fixture_parses() {
+        let data = include_str!("../tests/data/ethbtc_agg_trades.json");
+        let trades = parse_agg_trades(data).unwrap();
+        assert_eq!(trades.len(), 3);
+        let first = &trades[0];
+        assert_eq!(first.trade_id, 362519180);
+        assert_eq!(first.price, "0.06829300");
+        assert_eq!(first.quantity, "0.16540000");
+        assert_eq!(first.quote_quantity, "0.01129566");
+        assert_eq!(first.time_milliseconds, 1652614347356);
+        assert!(!first.is_buyer_maker);
+        assert!(trades[1].is_buyer_maker);
+        // ids and times go up, so the usual sorting and merging applies
+        let db = Db::from_trades_unsorted(trades).unwrap();
+        assert!(db.validate().is_ok());
+    }
+
+    #[test]
+    fn unparseable_agg_trades_are_errors() {
+        let data = r#"[{"a":1,"p":"0.068","q":"1e","T":1652614347356,"m":false,"M":true}]"#;
+        assert!(parse_agg_trades(data).is_err());
+        let data = r#"[{"a":1,"p":"","q":"1.0","T":1652614347356,"m":false,"M":true}]"#;
+        assert!(parse_agg_trades(data).is_err());
+    }
 }
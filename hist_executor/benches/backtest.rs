@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hist_executor::{
+    Balance, ExecutionModel, Executor, FixedBpsSlippage, FlatFee, RandomStrategy, SimulationConfig,
+    StrategyConfig,
+};
+
+const SAMPLE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../db/benches/data/ethbtc_sample.json"
+);
+
+// the whole strategy loop of a run: decisions, fills, fees and slippage over
+// every trade of the sample, with nothing recorded or printed
+fn bench_backtest(c: &mut Criterion) {
+    let executor = Executor::new(SAMPLE, "ETHBTC", db::BadPricePolicy::Reject).unwrap();
+    let len = db::Db::new(&SAMPLE).unwrap().get_data_len();
+    let execution = ExecutionModel {
+        fee_model: Box::new(FlatFee { fee: 0.001 }),
+        slippage_model: Box::new(FixedBpsSlippage { bps: 1.0 }),
+        maker_fills: false,
+        order_book: None,
+    };
+    let config = StrategyConfig {
+        momentum_lookback: 50,
+        momentum_entry_threshold: 0.001,
+        momentum_exit_threshold: 0.0,
+        rsi_period: 14,
+        rsi_oversold: 30.0,
+        rsi_overbought: 70.0,
+        trailing_stop_pct: 1.0,
+        static_avg_threshold_pct: 1.0,
+        vol_window: 50,
+        vol_base_threshold: 0.001,
+        vol_sensitivity: 1.0,
+        ensemble_members: Vec::new(),
+        resume_state: None,
+    };
+    let simulation = SimulationConfig {
+        start_balance: Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        },
+        start_position: None,
+        time_window: None,
+        trade_count: None,
+        max_alloc: 1.0,
+        fill_delay: 0,
+        cooldown: 0,
+        min_notional: 0.0,
+        max_total_fees: None,
+        final_liquidation: true,
+        warmup: 0,
+        record_equity: false,
+        record_trades: false,
+        record_events: false,
+        record_signals: false,
+        signals_only: false,
+        best_match_only: false,
+        metrics: None,
+        verbose: false,
+    };
+    let mut group = c.benchmark_group("backtest");
+    group.throughput(Throughput::Elements(len as u64));
+    group.bench_function("simulate_range::<RandomStrategy>", |b| {
+        b.iter(|| {
+            executor.simulate_range::<RandomStrategy>(0, len, &execution, &config, &simulation)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_backtest);
+criterion_main!(benches);
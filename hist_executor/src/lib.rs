@@ -0,0 +1,5102 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use plotters::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use structopt::StructOpt;
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct Balance {
+    pub base_balance: f64,
+    pub quote_balance: f64,
+}
+
+impl Balance {
+    // is_maker: the order rested on the book and was filled by someone else
+    fn buy(&mut self, base_quantity: f64, fee_model: &dyn FeeModel, is_maker: bool, price: f64) {
+        if base_quantity < 0.0 {
+            panic!("CHEETAH!");
+        }
+        let fee = fee_model.fee_for(&TradeAction::BuyQuote { base_quantity }, is_maker);
+        self.base_balance -= base_quantity;
+        let quote_diff: f64 = base_quantity * price * (1.0 - fee);
+        self.quote_balance += quote_diff;
+        if self.base_balance < 0.0 {
+            panic!("base_balance < 0! {}", self.base_balance)
+        }
+        if self.quote_balance < 0.0 {
+            panic!("quote_balance < 0! {}", self.quote_balance)
+        }
+    }
+    fn sell(&mut self, quote_quantity: f64, fee_model: &dyn FeeModel, is_maker: bool, price: f64) {
+        if quote_quantity < 0.0 {
+            panic!("CHEETAH!");
+        }
+        let fee = fee_model.fee_for(&TradeAction::SellQuote { quote_quantity }, is_maker);
+        let base_diff = quote_quantity * 1.0 / price * (1.0 - fee);
+        self.quote_balance -= quote_quantity;
+        self.base_balance += base_diff;
+        if self.base_balance < 0.0 {
+            panic!("base_balance < 0! {}", self.base_balance)
+        }
+        if self.quote_balance < 0.0 {
+            panic!("quote_balance < 0! {}", self.quote_balance)
+        }
+    }
+    // like buy, but takes liquidity at the book's average price; the fill holds
+    // what was actually traded, which is less than requested if the book is too shallow
+    fn buy_from_book(
+        &mut self,
+        base_quantity: f64,
+        fee_model: &dyn FeeModel,
+        book: &OrderBook,
+    ) -> Fill {
+        let fill = book.fill_base(base_quantity);
+        if fill.base_quantity > 0.0 {
+            self.buy(fill.base_quantity, fee_model, false, fill.average_price());
+        }
+        fill
+    }
+    // like sell, but fills at the book's average price
+    fn sell_from_book(
+        &mut self,
+        quote_quantity: f64,
+        fee_model: &dyn FeeModel,
+        book: &OrderBook,
+    ) -> Fill {
+        let fill = book.fill_quote(quote_quantity);
+        if fill.quote_quantity > 0.0 {
+            self.sell(fill.quote_quantity, fee_model, false, fill.average_price());
+        }
+        fill
+    }
+}
+
+pub struct OrderBookLevel {
+    pub price: f64,    // quote per base
+    pub quantity: f64, // base
+}
+
+pub struct OrderBook {
+    pub bids: Vec<OrderBookLevel>, // best (highest) price first
+    pub asks: Vec<OrderBookLevel>, // best (lowest) price first
+}
+
+struct Fill {
+    base_quantity: f64,
+    quote_quantity: f64,
+}
+
+impl Fill {
+    // volume-weighted average price, quote per base
+    fn average_price(&self) -> f64 {
+        self.quote_quantity / self.base_quantity
+    }
+}
+
+impl OrderBook {
+    // sell up to base_quantity into the bids
+    fn fill_base(&self, base_quantity: f64) -> Fill {
+        let mut fill = Fill {
+            base_quantity: 0.0,
+            quote_quantity: 0.0,
+        };
+        for level in &self.bids {
+            let remaining = base_quantity - fill.base_quantity;
+            if remaining <= 0.0 {
+                break;
+            }
+            let taken = f64::min(remaining, level.quantity);
+            fill.base_quantity += taken;
+            fill.quote_quantity += taken * level.price;
+        }
+        fill
+    }
+    // spend up to quote_quantity on the asks
+    fn fill_quote(&self, quote_quantity: f64) -> Fill {
+        let mut fill = Fill {
+            base_quantity: 0.0,
+            quote_quantity: 0.0,
+        };
+        for level in &self.asks {
+            let remaining = quote_quantity - fill.quote_quantity;
+            if remaining <= 0.0 {
+                break;
+            }
+            let taken = f64::min(remaining, level.quantity * level.price);
+            fill.quote_quantity += taken;
+            fill.base_quantity += taken / level.price;
+        }
+        fill
+    }
+}
+
+// balances for any number of assets, for strategies trading more than one pair
+#[derive(Default)]
+pub struct Portfolio {
+    balances: HashMap<String, f64>,
+}
+
+impl Portfolio {
+    pub fn new() -> Portfolio {
+        Portfolio {
+            balances: HashMap::new(),
+        }
+    }
+    pub fn get(&self, asset: &str) -> f64 {
+        *self.balances.get(asset).unwrap_or(&0.0)
+    }
+    pub fn deposit(&mut self, asset: &str, quantity: f64) {
+        *self.balances.entry(asset.to_string()).or_insert(0.0) += quantity;
+    }
+    fn check_non_negative(&self, asset: &str) {
+        if self.get(asset) < 0.0 {
+            panic!("{asset} balance < 0! {}", self.get(asset))
+        }
+    }
+    // same semantics as Balance::buy for the base_asset/quote_asset pair
+    pub fn buy(
+        &mut self,
+        base_asset: &str,
+        quote_asset: &str,
+        base_quantity: f64,
+        fee_model: &dyn FeeModel,
+        price: f64,
+    ) {
+        if base_quantity < 0.0 {
+            panic!("CHEETAH!");
+        }
+        let fee = fee_model.fee_for(&TradeAction::BuyQuote { base_quantity }, false);
+        self.deposit(base_asset, -base_quantity);
+        self.deposit(quote_asset, base_quantity * price * (1.0 - fee));
+        self.check_non_negative(base_asset);
+        self.check_non_negative(quote_asset);
+    }
+    // same semantics as Balance::sell for the base_asset/quote_asset pair
+    pub fn sell(
+        &mut self,
+        base_asset: &str,
+        quote_asset: &str,
+        quote_quantity: f64,
+        fee_model: &dyn FeeModel,
+        price: f64,
+    ) {
+        if quote_quantity < 0.0 {
+            panic!("CHEETAH!");
+        }
+        let fee = fee_model.fee_for(&TradeAction::SellQuote { quote_quantity }, false);
+        self.deposit(quote_asset, -quote_quantity);
+        self.deposit(base_asset, quote_quantity / price * (1.0 - fee));
+        self.check_non_negative(base_asset);
+        self.check_non_negative(quote_asset);
+    }
+}
+
+#[derive(Copy, Clone, Serialize)]
+pub enum TradeAction {
+    Pass,
+    BuyQuote { base_quantity: f64 }, // exchange base_quantity of base symbol for last_price * quote_quantity * (1 - fee)
+    SellQuote { quote_quantity: f64 }, // exchange quote_quantity of quote symbol for 1/last_price * quote_quantity * (1 - fee)
+    BuyFraction { fraction: f64 },     // BuyQuote with fraction of the base balance at fill time
+    SellFraction { fraction: f64 },    // SellQuote with fraction of the quote balance at fill time
+}
+
+impl TradeAction {
+    // turns fractions into absolute quantities of the current balance
+    fn resolved(self, balance: &Balance) -> TradeAction {
+        match self {
+            TradeAction::BuyFraction { fraction } => TradeAction::BuyQuote {
+                base_quantity: balance.base_balance * fraction,
+            },
+            TradeAction::SellFraction { fraction } => TradeAction::SellQuote {
+                quote_quantity: balance.quote_balance * fraction,
+            },
+            action => action,
+        }
+    }
+    // clamps the traded quantity to max_alloc of the balance it's taken from;
+    // the result is always Pass, BuyQuote or SellQuote
+    fn capped(self, balance: &Balance, max_alloc: f64) -> TradeAction {
+        match self {
+            TradeAction::Pass => TradeAction::Pass,
+            TradeAction::BuyFraction { .. } | TradeAction::SellFraction { .. } => {
+                self.resolved(balance).capped(balance, max_alloc)
+            }
+            TradeAction::BuyQuote { base_quantity } => TradeAction::BuyQuote {
+                base_quantity: f64::min(base_quantity, balance.base_balance * max_alloc),
+            },
+            TradeAction::SellQuote { quote_quantity } => TradeAction::SellQuote {
+                quote_quantity: f64::min(quote_quantity, balance.quote_balance * max_alloc),
+            },
+        }
+    }
+    // value of a resolved action in quote at price, what binance calls the
+    // notional and checks against a pair's minimum; 0 for Pass
+    fn notional(&self, price: f64) -> f64 {
+        match *self {
+            TradeAction::BuyQuote { base_quantity } => base_quantity * price,
+            TradeAction::SellQuote { quote_quantity } => quote_quantity,
+            _ => 0.0,
+        }
+    }
+    // the direction alone, as written to signal logs
+    fn side(&self) -> &'static str {
+        match self {
+            TradeAction::Pass => "pass",
+            TradeAction::BuyQuote { .. } | TradeAction::BuyFraction { .. } => "buy",
+            TradeAction::SellQuote { .. } | TradeAction::SellFraction { .. } => "sell",
+        }
+    }
+}
+
+// Fees are fractions of the filled amount. A negative fee is a rebate, which
+// buy and sell add to the received amount instead of taking off.
+pub trait FeeModel {
+    fn fee_for(&self, action: &TradeAction, is_maker: bool) -> f64;
+}
+
+pub struct FlatFee {
+    pub fee: f64,
+}
+
+impl FeeModel for FlatFee {
+    fn fee_for(&self, _action: &TradeAction, _is_maker: bool) -> f64 {
+        self.fee
+    }
+}
+
+// most binance pays back to makers on the pairs that have rebates
+const MAX_MAKER_REBATE: f64 = 0.0005;
+
+struct MakerTakerFee {
+    maker_fee: f64, // negative for a rebate, down to -MAX_MAKER_REBATE
+    taker_fee: f64,
+    discount: f64, // fraction taken off both fees, e.g. 0.25 when paying fees in BNB
+}
+
+impl FeeModel for MakerTakerFee {
+    fn fee_for(&self, _action: &TradeAction, is_maker: bool) -> f64 {
+        let fee = if is_maker {
+            self.maker_fee
+        } else {
+            self.taker_fee
+        };
+        if fee < 0.0 {
+            return fee; // the discount is on fees paid, rebates aren't affected
+        }
+        fee * (1.0 - self.discount)
+    }
+}
+
+// the fee flags as given, from which the fee model is built
+#[derive(Clone, Serialize, Deserialize)]
+struct FeeSettings {
+    fee: f64,
+    maker_fee: Option<f64>,
+    taker_fee: Option<f64>,
+    buy_fee_bps: Option<f64>,
+    sell_fee_bps: Option<f64>,
+    discount: f64,
+    maker_fills: bool,
+}
+
+impl FeeSettings {
+    // per side fees win over maker/taker ones, which win over the flat fee
+    fn fee_model(&self) -> Box<dyn FeeModel> {
+        let fee = self.fee;
+        if self.buy_fee_bps.is_some() || self.sell_fee_bps.is_some() {
+            Box::new(FeeSpec {
+                buy_bps: self.buy_fee_bps.unwrap_or(fee * 10000.0),
+                sell_bps: self.sell_fee_bps.unwrap_or(fee * 10000.0),
+                discount: self.discount,
+            })
+        } else if self.maker_fee.is_some() || self.taker_fee.is_some() {
+            Box::new(MakerTakerFee {
+                maker_fee: self.maker_fee.unwrap_or(fee),
+                taker_fee: self.taker_fee.unwrap_or(fee),
+                discount: self.discount,
+            })
+        } else {
+            Box::new(FlatFee {
+                fee: fee * (1.0 - self.discount),
+            })
+        }
+    }
+}
+
+// separate fees per side, in basis points (10 bps = 0.1%)
+struct FeeSpec {
+    buy_bps: f64,
+    sell_bps: f64,
+    discount: f64,
+}
+
+impl FeeModel for FeeSpec {
+    fn fee_for(&self, action: &TradeAction, _is_maker: bool) -> f64 {
+        let bps = match action {
+            TradeAction::BuyQuote { .. } | TradeAction::BuyFraction { .. } => self.buy_bps,
+            TradeAction::SellQuote { .. } | TradeAction::SellFraction { .. } => self.sell_bps,
+            // a round trip pays both, so plan around the more expensive side
+            TradeAction::Pass => f64::max(self.buy_bps, self.sell_bps),
+        };
+        bps / 10000.0 * (1.0 - self.discount)
+    }
+}
+
+pub trait SlippageModel {
+    // price the order actually fills at, given the last observed trade price
+    fn fill_price(&self, action: &TradeAction, price: f64) -> f64;
+}
+
+pub struct FixedBpsSlippage {
+    pub bps: f64,
+}
+
+impl SlippageModel for FixedBpsSlippage {
+    // BuyQuote receives price quote per base, so a worse fill is a lower price;
+    // SellQuote pays price quote per base, so a worse fill is a higher price
+    fn fill_price(&self, action: &TradeAction, price: f64) -> f64 {
+        let penalty = self.bps / 10000.0;
+        match action {
+            TradeAction::Pass => price,
+            TradeAction::BuyQuote { .. } | TradeAction::BuyFraction { .. } => {
+                price * (1.0 - penalty)
+            }
+            TradeAction::SellQuote { .. } | TradeAction::SellFraction { .. } => {
+                price * (1.0 + penalty)
+            }
+        }
+    }
+}
+
+pub struct ExecutionModel {
+    pub fee_model: Box<dyn FeeModel>,
+    pub slippage_model: Box<dyn SlippageModel>,
+    pub maker_fills: bool, // orders rest on the book as limit orders and pay the maker fee
+    // when set, orders take liquidity level by level at the book's average price,
+    // possibly filling partially, instead of going through slippage_model
+    pub order_book: Option<OrderBook>,
+}
+
+// quote already held when the run starts, bought before it at entry_price
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub quantity: f64, // in quote, on top of start_balance.quote_balance
+    pub entry_price: f64,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct SimulationConfig {
+    pub start_balance: Balance,
+    pub start_position: Option<Position>, // realized pnl counts from its entry price
+    pub time_window: Option<(i64, i64)>,  // start_ms, end_ms; random index range if not set
+    pub trade_count: Option<usize>, // trades per run from a random start instead of a random end
+    pub max_alloc: f64,             // max fraction of a balance a single action may trade
+    pub fill_delay: usize,          // trades between a decision and its fill
+    pub cooldown: usize,            // trades after a fill during which further fills are dropped
+    pub min_notional: f64, // fills worth less in quote are dropped, like binance rejects them
+    pub max_total_fees: Option<f64>, // kill-switch: fills are dropped once this much base went to fees
+    pub final_liquidation: bool,     // sell all quote at the end, so runs compare in base only
+    pub warmup: usize,               // trades before the start fed through consume_data only
+    pub record_equity: bool,
+    pub record_trades: bool,
+    pub record_events: bool,
+    pub record_signals: bool,
+    // decisions are only recorded as signals, never filled, so the balance
+    // stays as it started; for judging signals apart from execution
+    pub signals_only: bool,
+    // Binance flags prints that didn't execute at the best available price;
+    // they can spike away from the book, so skipping them keeps strategies
+    // from reacting to (and filling at) prices nobody could have traded at
+    pub best_match_only: bool,
+    #[serde(skip)]
+    pub metrics: Option<&'static Metrics>, // updated on every trade while the run goes
+    pub verbose: bool,
+}
+
+impl SimulationConfig {
+    // start_balance plus the quote of start_position
+    fn opening_balance(&self) -> Balance {
+        let mut balance = self.start_balance;
+        if let Some(position) = self.start_position {
+            balance.quote_balance += position.quantity;
+        }
+        balance
+    }
+}
+
+#[derive(Copy, Clone)]
+struct EquityPoint {
+    time_milliseconds: i64,
+    base_balance: f64,
+    quote_balance: f64,
+    mark_price: f64,
+}
+
+impl EquityPoint {
+    // quote holdings valued at the current price, in base units
+    fn equity(&self) -> f64 {
+        self.base_balance + self.quote_balance / self.mark_price
+    }
+}
+
+// a react_to_data result, recorded every tick whether or not it was filled
+struct SignalRecord {
+    time_milliseconds: i64,
+    signal: &'static str,   // buy, sell or pass
+    indicator: Option<f64>, // see Strategy::indicator
+}
+
+struct TradeRecord {
+    time_milliseconds: i64,
+    action: &'static str, // buy or sell
+    price: f64,           // fill price
+    quantity: f64,        // base for buys, quote for sells
+    base_balance_after: f64,
+    quote_balance_after: f64,
+}
+
+// Splits pnl, in base units, into the part locked in by sells and the part
+// still riding on quote holdings.
+struct PnlTracker {
+    cost_basis: f64, // base paid for the quote currently held
+    realized_pnl: f64,
+}
+
+impl PnlTracker {
+    fn on_buy(&mut self, base_spent: f64) {
+        self.cost_basis += base_spent;
+    }
+    // quote_before is the quote balance before the sell
+    fn on_sell(&mut self, quote_sold: f64, quote_before: f64, base_received: f64) {
+        let released = if quote_before > 0.0 {
+            self.cost_basis * quote_sold / quote_before
+        } else {
+            0.0
+        };
+        self.cost_basis -= released;
+        self.realized_pnl += base_received - released;
+    }
+    // quote holdings marked to price, fees and slippage of closing them ignored
+    fn unrealized_pnl(&self, quote_balance: f64, price: f64) -> f64 {
+        quote_balance / price - self.cost_basis
+    }
+}
+
+// A position opened from no quote and closed back to none. Excursions are
+// the position's unrealized return in base between entry and exit, marked at
+// market prices: quote gains base value as the price falls, so the favorable
+// excursion comes from the lowest price and the adverse one from the highest.
+struct RoundTrip {
+    max_favorable_excursion: f64, // >= 0
+    max_adverse_excursion: f64,   // <= 0
+}
+
+struct ExcursionTracker {
+    open: Option<(f64, f64, f64)>, // entry price, mfe, mae so far
+    round_trips: Vec<RoundTrip>,
+}
+
+impl ExcursionTracker {
+    fn on_price(&mut self, price: f64) {
+        if let Some((entry_price, mfe, mae)) = &mut self.open {
+            let excursion = *entry_price / price - 1.0;
+            *mfe = f64::max(*mfe, excursion);
+            *mae = f64::min(*mae, excursion);
+        }
+    }
+    // after every fill, with the quote balance before and after it
+    fn on_fill(&mut self, price: f64, quote_before: f64, quote_after: f64) {
+        if self.open.is_none() && quote_before == 0.0 && quote_after > 0.0 {
+            self.open = Some((price, 0.0, 0.0));
+        } else if quote_after == 0.0 {
+            self.close();
+        }
+    }
+    fn close(&mut self) {
+        if let Some((_, mfe, mae)) = self.open.take() {
+            self.round_trips.push(RoundTrip {
+                max_favorable_excursion: mfe,
+                max_adverse_excursion: mae,
+            });
+        }
+    }
+}
+
+// what happened during a run, in order, for analysis after the fact
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum Event {
+    // a strategy decision other than Pass, as returned by react_to_data
+    Decision {
+        time_milliseconds: i64,
+        action: TradeAction,
+    },
+    Fill {
+        time_milliseconds: i64,
+        action: &'static str, // buy or sell
+        price: f64,
+        quantity: f64, // base for buys, quote for sells
+    },
+    // a decision that came due but was dropped
+    Rejected {
+        time_milliseconds: i64,
+        action: TradeAction,
+        reason: &'static str,
+    },
+    // the forced sell of all quote at the end of the run
+    Liquidation {
+        time_milliseconds: i64,
+        price: f64,
+        quote_quantity: f64,
+    },
+}
+
+pub struct BacktestResult {
+    start_base_balance: f64, // start_balance valued in base at the first price
+    final_equity: f64,       // end balance valued in base at the last price
+    equity_curve: Vec<EquityPoint>, // one point per tick, empty unless record_equity is set
+    trades: Vec<TradeRecord>, // one record per fill, empty unless record_trades is set
+    signals: Vec<SignalRecord>, // one record per tick, empty unless record_signals is set
+    events: Vec<Event>,      // empty unless record_events is set
+    round_trips: Vec<RoundTrip>, // a position still open at the end isn't included
+    time_span_milliseconds: i64, // from the first to the last trade of the window
+    annualized_return: Option<f64>, // None if the window is too short to annualize
+    realized_pnl: f64,       // in base, from sells before the final liquidation
+    unrealized_pnl: f64,     // in base, of quote still held at the end of the window
+    suppressed_actions: usize, // buys and sells dropped because of the cooldown
+    below_min_notional: usize, // buys and sells dropped for being smaller than min_notional
+    over_fee_cap: usize,     // buys and sells dropped after max_total_fees was reached
+    final_state: Option<serde_json::Value>, // of the strategy at the end, see Strategy::save_state
+}
+
+const MILLISECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0 * 1000.0;
+// below this, small returns annualize to absurd numbers
+const MIN_ANNUALIZED_SPAN_MILLISECONDS: i64 = 24 * 3600 * 1000;
+
+// compound annual growth rate, e.g. 0.1 for +10% a year
+fn annualized_return(
+    start_equity: f64,
+    end_equity: f64,
+    time_span_milliseconds: i64,
+) -> Option<f64> {
+    if time_span_milliseconds < MIN_ANNUALIZED_SPAN_MILLISECONDS || start_equity <= 0.0 {
+        return None;
+    }
+    let years = time_span_milliseconds as f64 / MILLISECONDS_PER_YEAR;
+    Some((end_equity / start_equity).powf(1.0 / years) - 1.0)
+}
+
+impl BacktestResult {
+    fn save_equity_csv<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "time,base_balance,quote_balance,mark_price,equity")?;
+        for point in &self.equity_curve {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                point.time_milliseconds,
+                point.base_balance,
+                point.quote_balance,
+                point.mark_price,
+                point.equity()
+            )?;
+        }
+        writer.flush()
+    }
+    // price on the left axis, equity on the right one, both over time
+    fn save_plot_svg<P: AsRef<Path>>(
+        &self,
+        filename: &P,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (first, last) = match (self.equity_curve.first(), self.equity_curve.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err("no equity curve to plot".into()),
+        };
+        let range = |values: &mut dyn Iterator<Item = f64>| {
+            values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| {
+                (f64::min(low, v), f64::max(high, v))
+            })
+        };
+        let (price_low, price_high) = range(&mut self.equity_curve.iter().map(|p| p.mark_price));
+        let (equity_low, equity_high) = range(&mut self.equity_curve.iter().map(|p| p.equity()));
+        let time_range = first.time_milliseconds..last.time_milliseconds;
+        let root = SVGBackend::new(filename.as_ref(), (1024, 576)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .right_y_label_area_size(60)
+            .build_cartesian_2d(time_range.clone(), price_low..price_high)?
+            .set_secondary_coord(time_range, equity_low..equity_high);
+        chart
+            .configure_mesh()
+            .x_desc("time, ms")
+            .y_desc("price")
+            .draw()?;
+        chart.configure_secondary_axes().y_desc("equity").draw()?;
+        chart
+            .draw_series(LineSeries::new(
+                self.equity_curve
+                    .iter()
+                    .map(|p| (p.time_milliseconds, p.mark_price)),
+                &BLUE,
+            ))?
+            .label("price")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+        chart
+            .draw_secondary_series(LineSeries::new(
+                self.equity_curve
+                    .iter()
+                    .map(|p| (p.time_milliseconds, p.equity())),
+                &RED,
+            ))?
+            .label("equity")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+        chart
+            .configure_series_labels()
+            .background_style(WHITE)
+            .border_style(BLACK)
+            .draw()?;
+        root.present()?;
+        Ok(())
+    }
+    fn save_events_json<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self.events)?;
+        writer.flush()
+    }
+    // null for strategies without state
+    fn save_state_json<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self.final_state)?;
+        writer.flush()
+    }
+    fn save_signals_csv<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "timestamp,signal,indicator")?;
+        for signal in &self.signals {
+            // an empty indicator for strategies without one, or before it's available
+            let indicator = signal.indicator.map_or(String::new(), |v| v.to_string());
+            writeln!(
+                writer,
+                "{},{},{}",
+                signal.time_milliseconds, signal.signal, indicator
+            )?;
+        }
+        writer.flush()
+    }
+    fn save_trades_csv<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        writeln!(
+            writer,
+            "timestamp,action,price,quantity,base_balance_after,quote_balance_after"
+        )?;
+        for trade in &self.trades {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                trade.time_milliseconds,
+                trade.action,
+                trade.price,
+                trade.quantity,
+                trade.base_balance_after,
+                trade.quote_balance_after
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StrategyConfig {
+    pub momentum_lookback: usize,
+    pub momentum_entry_threshold: f64,
+    pub momentum_exit_threshold: f64,
+    pub rsi_period: usize,
+    pub rsi_oversold: f64,
+    pub rsi_overbought: f64,
+    pub trailing_stop_pct: f64,
+    pub static_avg_threshold_pct: f64,
+    pub vol_window: usize,
+    pub vol_base_threshold: f64,
+    pub vol_sensitivity: f64,
+    pub ensemble_members: Vec<String>, // strategy names, see new_strategy
+    pub resume_state: Option<serde_json::Value>, // from Strategy::save_state, loaded after the warm-up
+}
+
+pub trait Strategy {
+    #[allow(clippy::new_ret_no_self)] // boxed, so strategies can be picked by name at runtime
+    fn new(balance: Balance, fee: f64, config: &StrategyConfig) -> Box<dyn Strategy>
+    where
+        Self: Sized;
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance, // new balances after previous action (if any)
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction;
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade); // view historical data, but can't react to it
+
+    // called once with the whole trading window (most recent first) before the
+    // first react_to_data, for strategies that fit something over all of it
+    fn on_window_start(&mut self, _data: &[db::HistoricalTrade]) {
+        // pass
+    }
+
+    // the value the strategy's decisions hinge on, as of the last react_to_data,
+    // for signal logs; None if the strategy has none or it isn't available yet
+    fn indicator(&self) -> Option<f64> {
+        None
+    }
+
+    // internal state (rolling windows, entry prices) to pick up from after a
+    // restart; None for strategies with nothing worth carrying over
+    fn save_state(&self) -> Option<serde_json::Value> {
+        None
+    }
+    // restores what save_state returned, replacing whatever the warm-up built
+    fn load_state(&mut self, _state: &serde_json::Value) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+fn parse_state<T: serde::de::DeserializeOwned>(state: &serde_json::Value) -> Result<T, String> {
+    T::deserialize(state).map_err(|e| e.to_string())
+}
+
+pub struct RandomStrategy {
+    balance: Balance,
+    last_buying_price: Option<f64>,
+    already_sold: bool,
+    fee: f64,
+}
+
+impl Strategy for RandomStrategy {
+    fn new(balance: Balance, fee: f64, _config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = RandomStrategy {
+            balance,
+            fee,
+            last_buying_price: None,
+            already_sold: false,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {
+        // pass
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        if self.already_sold {
+            return TradeAction::BuyQuote { base_quantity: 0.0 };
+        }
+        /*
+            buy for all, then wait until price increased and sell all
+        */
+        match self.last_buying_price {
+            None => {
+                self.last_buying_price = Some(new_data.get_price() * (1.0 + self.fee));
+                TradeAction::BuyQuote {
+                    base_quantity: self.balance.base_balance,
+                }
+            }
+            Some(last_buying_price) => {
+                let new_price = new_data.get_price();
+                if new_price * (1.0 + self.fee) < last_buying_price * (1.0 - self.fee) {
+                    self.already_sold = true;
+                    return TradeAction::SellQuote {
+                        quote_quantity: self.balance.quote_balance,
+                    };
+                }
+                TradeAction::Pass
+            }
+        }
+    }
+}
+
+struct MomentumStrategy {
+    balance: Balance,
+    prices: VecDeque<f64>, // last lookback + 1 prices, oldest first
+    lookback: usize,
+    entry_threshold: f64,
+    exit_threshold: f64,
+    in_position: bool,
+}
+
+impl MomentumStrategy {
+    // rate of change over the lookback window: price_now / price_n_ago - 1
+    fn momentum(&self) -> Option<f64> {
+        if self.prices.len() <= self.lookback {
+            return None;
+        }
+        let oldest = self.prices.front()?;
+        let newest = self.prices.back()?;
+        Some(newest / oldest - 1.0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MomentumState {
+    prices: VecDeque<f64>,
+    in_position: bool,
+}
+
+impl Strategy for MomentumStrategy {
+    fn new(balance: Balance, _fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = MomentumStrategy {
+            balance,
+            prices: VecDeque::with_capacity(config.momentum_lookback + 1),
+            lookback: config.momentum_lookback,
+            entry_threshold: config.momentum_entry_threshold,
+            exit_threshold: config.momentum_exit_threshold,
+            in_position: false,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade) {
+        if self.prices.len() > self.lookback {
+            self.prices.pop_front();
+        }
+        self.prices.push_back(new_data.get_price());
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        self.consume_data(new_data);
+        let momentum = match self.momentum() {
+            None => return TradeAction::Pass,
+            Some(momentum) => momentum,
+        };
+        if !self.in_position && momentum > self.entry_threshold {
+            self.in_position = true;
+            return TradeAction::BuyQuote {
+                base_quantity: self.balance.base_balance,
+            };
+        }
+        if self.in_position && momentum < self.exit_threshold {
+            self.in_position = false;
+            return TradeAction::SellQuote {
+                quote_quantity: self.balance.quote_balance,
+            };
+        }
+        TradeAction::Pass
+    }
+    fn indicator(&self) -> Option<f64> {
+        self.momentum()
+    }
+    fn save_state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(MomentumState {
+            prices: self.prices.clone(),
+            in_position: self.in_position,
+        })
+        .ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let state: MomentumState = parse_state(state)?;
+        self.prices = state.prices;
+        self.in_position = state.in_position;
+        Ok(())
+    }
+}
+
+struct RsiStrategy {
+    balance: Balance,
+    period: usize,
+    oversold: f64,
+    overbought: f64,
+    last_price: Option<f64>,
+    changes_seen: usize,
+    avg_gain: f64, // Wilder-smoothed, plain average during the first period
+    avg_loss: f64,
+    rsi: Option<f64>,
+    prev_rsi: Option<f64>,
+    in_position: bool,
+}
+
+impl RsiStrategy {
+    fn update_rsi(&mut self, price: f64) {
+        let last_price = match self.last_price {
+            None => {
+                self.last_price = Some(price);
+                return;
+            }
+            Some(last_price) => last_price,
+        };
+        self.last_price = Some(price);
+        let change = price - last_price;
+        let gain = f64::max(change, 0.0);
+        let loss = f64::max(-change, 0.0);
+        self.changes_seen += 1;
+        let period = self.period as f64;
+        if self.changes_seen <= self.period {
+            // seed the averages with a simple mean of the first period changes
+            self.avg_gain += gain / period;
+            self.avg_loss += loss / period;
+        } else {
+            self.avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+            self.avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+        }
+        if self.changes_seen < self.period {
+            return;
+        }
+        self.prev_rsi = self.rsi;
+        self.rsi = Some(if self.avg_loss == 0.0 {
+            100.0
+        } else {
+            100.0 - 100.0 / (1.0 + self.avg_gain / self.avg_loss)
+        });
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RsiState {
+    last_price: Option<f64>,
+    changes_seen: usize,
+    avg_gain: f64,
+    avg_loss: f64,
+    rsi: Option<f64>,
+    prev_rsi: Option<f64>,
+    in_position: bool,
+}
+
+impl Strategy for RsiStrategy {
+    fn new(balance: Balance, _fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = RsiStrategy {
+            balance,
+            period: config.rsi_period,
+            oversold: config.rsi_oversold,
+            overbought: config.rsi_overbought,
+            last_price: None,
+            changes_seen: 0,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+            rsi: None,
+            prev_rsi: None,
+            in_position: false,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade) {
+        self.update_rsi(new_data.get_price());
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        self.consume_data(new_data);
+        let (prev_rsi, rsi) = match (self.prev_rsi, self.rsi) {
+            (Some(prev_rsi), Some(rsi)) => (prev_rsi, rsi),
+            _ => return TradeAction::Pass,
+        };
+        if !self.in_position && prev_rsi < self.oversold && rsi >= self.oversold {
+            self.in_position = true;
+            return TradeAction::BuyQuote {
+                base_quantity: self.balance.base_balance,
+            };
+        }
+        if self.in_position && prev_rsi > self.overbought && rsi <= self.overbought {
+            self.in_position = false;
+            return TradeAction::SellQuote {
+                quote_quantity: self.balance.quote_balance,
+            };
+        }
+        TradeAction::Pass
+    }
+    fn indicator(&self) -> Option<f64> {
+        self.rsi
+    }
+    fn save_state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(RsiState {
+            last_price: self.last_price,
+            changes_seen: self.changes_seen,
+            avg_gain: self.avg_gain,
+            avg_loss: self.avg_loss,
+            rsi: self.rsi,
+            prev_rsi: self.prev_rsi,
+            in_position: self.in_position,
+        })
+        .ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let state: RsiState = parse_state(state)?;
+        self.last_price = state.last_price;
+        self.changes_seen = state.changes_seen;
+        self.avg_gain = state.avg_gain;
+        self.avg_loss = state.avg_loss;
+        self.rsi = state.rsi;
+        self.prev_rsi = state.prev_rsi;
+        self.in_position = state.in_position;
+        Ok(())
+    }
+}
+
+struct TrailingStopStrategy {
+    balance: Balance,
+    trailing_pct: f64, // fraction the price may retrace from its high before selling
+    in_position: bool,
+    high_since_entry: f64,
+    high_at_exit: Option<f64>, // re-enter once the price makes a new high above this
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrailingStopState {
+    in_position: bool,
+    high_since_entry: f64,
+    high_at_exit: Option<f64>,
+}
+
+impl Strategy for TrailingStopStrategy {
+    fn new(balance: Balance, _fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = TrailingStopStrategy {
+            balance,
+            trailing_pct: config.trailing_stop_pct,
+            in_position: false,
+            high_since_entry: 0.0,
+            high_at_exit: None,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {
+        // pass
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        let price = new_data.get_price();
+        if self.in_position {
+            self.high_since_entry = f64::max(self.high_since_entry, price);
+            if price <= self.high_since_entry * (1.0 - self.trailing_pct) {
+                self.in_position = false;
+                self.high_at_exit = Some(self.high_since_entry);
+                return TradeAction::SellQuote {
+                    quote_quantity: self.balance.quote_balance,
+                };
+            }
+            return TradeAction::Pass;
+        }
+        let should_enter = match self.high_at_exit {
+            None => true,
+            Some(high_at_exit) => price > high_at_exit,
+        };
+        if should_enter {
+            self.in_position = true;
+            self.high_since_entry = price;
+            return TradeAction::BuyQuote {
+                base_quantity: self.balance.base_balance,
+            };
+        }
+        TradeAction::Pass
+    }
+    fn save_state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(TrailingStopState {
+            in_position: self.in_position,
+            high_since_entry: self.high_since_entry,
+            high_at_exit: self.high_at_exit,
+        })
+        .ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let state: TrailingStopState = parse_state(state)?;
+        self.in_position = state.in_position;
+        self.high_since_entry = state.high_since_entry;
+        self.high_at_exit = state.high_at_exit;
+        Ok(())
+    }
+}
+
+struct StaticAvgStrategy {
+    balance: Balance,
+    last_buying_price: Option<f64>,
+    already_sold: bool,
+    fee: f64,
+    threshold_pct: f64,
+    warmup_sum: f64,
+    warmup_count: usize,
+    average: Option<f64>, // frozen at the first react_to_data
+}
+
+#[derive(Serialize, Deserialize)]
+struct StaticAvgState {
+    last_buying_price: Option<f64>,
+    already_sold: bool,
+    warmup_sum: f64,
+    warmup_count: usize,
+    average: Option<f64>,
+}
+
+impl Strategy for StaticAvgStrategy {
+    fn new(balance: Balance, fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = StaticAvgStrategy {
+            balance,
+            fee,
+            threshold_pct: config.static_avg_threshold_pct,
+            last_buying_price: None,
+            already_sold: false,
+            warmup_sum: 0.0,
+            warmup_count: 0,
+            average: None,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade) {
+        if self.average.is_none() {
+            self.warmup_sum += new_data.get_price();
+            self.warmup_count += 1;
+        }
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        if self.average.is_none() && self.warmup_count > 0 {
+            self.average = Some(self.warmup_sum / self.warmup_count as f64);
+        }
+        let average = match self.average {
+            None => return TradeAction::Pass, // no warm-up data to average over
+            Some(average) => average,
+        };
+        if self.already_sold {
+            return TradeAction::Pass;
+        }
+        /*
+            buy once threshold_pct below the warm-up average, sell once threshold_pct above it
+        */
+        let new_price = new_data.get_price();
+        match self.last_buying_price {
+            None => {
+                if new_price * (1.0 + self.fee) < average * (1.0 - self.threshold_pct) {
+                    self.last_buying_price = Some(new_price * (1.0 + self.fee));
+                    return TradeAction::BuyQuote {
+                        base_quantity: self.balance.base_balance,
+                    };
+                }
+                TradeAction::Pass
+            }
+            Some(_) => {
+                if new_price * (1.0 - self.fee) > average * (1.0 + self.threshold_pct) {
+                    self.already_sold = true;
+                    return TradeAction::SellQuote {
+                        quote_quantity: self.balance.quote_balance,
+                    };
+                }
+                TradeAction::Pass
+            }
+        }
+    }
+    fn indicator(&self) -> Option<f64> {
+        self.average
+    }
+    fn save_state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(StaticAvgState {
+            last_buying_price: self.last_buying_price,
+            already_sold: self.already_sold,
+            warmup_sum: self.warmup_sum,
+            warmup_count: self.warmup_count,
+            average: self.average,
+        })
+        .ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let state: StaticAvgState = parse_state(state)?;
+        self.last_buying_price = state.last_buying_price;
+        self.already_sold = state.already_sold;
+        self.warmup_sum = state.warmup_sum;
+        self.warmup_count = state.warmup_count;
+        self.average = state.average;
+        Ok(())
+    }
+}
+
+// Time-weighted entry: spends the base balance in equal parts, one per trade of
+// the window, so the position is built at the window's average price instead
+// of a single one. Needs on_window_start to know how many trades there are.
+struct TwapStrategy {
+    balance: Balance,
+    ticks_left: usize, // trades of the window not yet reacted to
+}
+
+impl Strategy for TwapStrategy {
+    fn new(balance: Balance, _fee: f64, _config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = TwapStrategy {
+            balance,
+            ticks_left: 0,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {
+        // pass
+    }
+    fn on_window_start(&mut self, data: &[db::HistoricalTrade]) {
+        self.ticks_left = data.len();
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        _new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        if self.ticks_left == 0 {
+            return TradeAction::Pass;
+        }
+        // what's left, spread over the trades that are left, so the last one
+        // spends the rest whatever earlier fills cost
+        let base_quantity = self.balance.base_balance / self.ticks_left as f64;
+        self.ticks_left -= 1;
+        TradeAction::BuyQuote { base_quantity }
+    }
+    fn indicator(&self) -> Option<f64> {
+        Some(self.ticks_left as f64)
+    }
+}
+
+// Momentum over the last window trades, with entry and exit thresholds that
+// widen with volatility: base_threshold + sensitivity * std of per-trade
+// returns over the window. Calm markets need only a small move to trigger,
+// volatile ones need a move that stands out from the noise.
+struct VolAdaptiveStrategy {
+    balance: Balance,
+    window: usize,
+    base_threshold: f64,
+    sensitivity: f64,
+    prices: VecDeque<f64>, // last window + 1 prices, oldest first
+    returns_sum: f64,      // of the window returns between consecutive prices
+    returns_sq_sum: f64,
+    in_position: bool,
+}
+
+impl VolAdaptiveStrategy {
+    fn volatility(&self) -> f64 {
+        let n = (self.prices.len() - 1) as f64;
+        let mean = self.returns_sum / n;
+        // running sums can drift slightly negative through rounding
+        f64::max(self.returns_sq_sum / n - mean * mean, 0.0).sqrt()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VolAdaptiveState {
+    prices: VecDeque<f64>,
+    returns_sum: f64,
+    returns_sq_sum: f64,
+    in_position: bool,
+}
+
+impl Strategy for VolAdaptiveStrategy {
+    fn new(balance: Balance, _fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        let strategy = VolAdaptiveStrategy {
+            balance,
+            window: config.vol_window,
+            base_threshold: config.vol_base_threshold,
+            sensitivity: config.vol_sensitivity,
+            prices: VecDeque::with_capacity(config.vol_window + 1),
+            returns_sum: 0.0,
+            returns_sq_sum: 0.0,
+            in_position: false,
+        };
+        Box::new(strategy)
+    }
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade) {
+        let price = new_data.get_price();
+        if let Some(last) = self.prices.back() {
+            let ret = price / last - 1.0;
+            self.returns_sum += ret;
+            self.returns_sq_sum += ret * ret;
+        }
+        self.prices.push_back(price);
+        if self.prices.len() > self.window + 1 {
+            let dropped = self.prices.pop_front().unwrap();
+            let ret = self.prices[0] / dropped - 1.0;
+            self.returns_sum -= ret;
+            self.returns_sq_sum -= ret * ret;
+        }
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        self.balance = new_balance;
+        self.consume_data(new_data);
+        if self.prices.len() <= self.window {
+            return TradeAction::Pass;
+        }
+        let momentum = self.prices.back().unwrap() / self.prices.front().unwrap() - 1.0;
+        let threshold = self.base_threshold + self.sensitivity * self.volatility();
+        if !self.in_position && momentum > threshold {
+            self.in_position = true;
+            return TradeAction::BuyQuote {
+                base_quantity: self.balance.base_balance,
+            };
+        }
+        if self.in_position && momentum < -threshold {
+            self.in_position = false;
+            return TradeAction::SellQuote {
+                quote_quantity: self.balance.quote_balance,
+            };
+        }
+        TradeAction::Pass
+    }
+    fn save_state(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(VolAdaptiveState {
+            prices: self.prices.clone(),
+            returns_sum: self.returns_sum,
+            returns_sq_sum: self.returns_sq_sum,
+            in_position: self.in_position,
+        })
+        .ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let state: VolAdaptiveState = parse_state(state)?;
+        self.prices = state.prices;
+        self.returns_sum = state.returns_sum;
+        self.returns_sq_sum = state.returns_sq_sum;
+        self.in_position = state.in_position;
+        Ok(())
+    }
+}
+
+// Acts on the majority vote of its members. Every member votes buy, sell or
+// pass each tick; buy and sell votes cancel each other out, and the ensemble
+// only trades when the net buy or sell votes are more than half of all members,
+// using the mean quantity of the winning side. Members keep their own state as
+// if their vote had been executed, even when it was outvoted.
+struct EnsembleStrategy {
+    members: Vec<Box<dyn Strategy>>,
+}
+
+impl EnsembleStrategy {
+    // the strategies named in config.ensemble_members, in order
+    fn members(
+        balance: Balance,
+        fee: f64,
+        config: &StrategyConfig,
+    ) -> Result<Vec<Box<dyn Strategy>>, String> {
+        config
+            .ensemble_members
+            .iter()
+            .map(|name| {
+                new_strategy(name, balance, fee, config)
+                    .ok_or_else(|| format!("unknown ensemble member: {name}"))
+            })
+            .collect()
+    }
+}
+
+impl Strategy for EnsembleStrategy {
+    fn new(balance: Balance, fee: f64, config: &StrategyConfig) -> Box<dyn Strategy> {
+        // the names were already checked by check_strategy before any run
+        let members =
+            EnsembleStrategy::members(balance, fee, config).unwrap_or_else(|e| panic!("{e}"));
+        Box::new(EnsembleStrategy { members })
+    }
+    fn consume_data(&mut self, new_data: &db::HistoricalTrade) {
+        for member in &mut self.members {
+            member.consume_data(new_data);
+        }
+    }
+    fn on_window_start(&mut self, data: &[db::HistoricalTrade]) {
+        for member in &mut self.members {
+            member.on_window_start(data);
+        }
+    }
+    // one entry per member, in order
+    fn save_state(&self) -> Option<serde_json::Value> {
+        let states: Vec<Option<serde_json::Value>> = self
+            .members
+            .iter()
+            .map(|member| member.save_state())
+            .collect();
+        serde_json::to_value(states).ok()
+    }
+    fn load_state(&mut self, state: &serde_json::Value) -> Result<(), String> {
+        let states: Vec<Option<serde_json::Value>> = parse_state(state)?;
+        if states.len() != self.members.len() {
+            return Err(format!(
+                "state for {} members, ensemble has {}",
+                states.len(),
+                self.members.len()
+            ));
+        }
+        for (member, state) in self.members.iter_mut().zip(states) {
+            if let Some(state) = state {
+                member.load_state(&state)?;
+            }
+        }
+        Ok(())
+    }
+    fn react_to_data(
+        &mut self,
+        new_balance: Balance,
+        new_data: &db::HistoricalTrade,
+    ) -> TradeAction {
+        let mut buy_quantities = Vec::new();
+        let mut sell_quantities = Vec::new();
+        for member in &mut self.members {
+            match member.react_to_data(new_balance, new_data) {
+                TradeAction::Pass => (),
+                TradeAction::BuyQuote { base_quantity } => buy_quantities.push(base_quantity),
+                TradeAction::SellQuote { quote_quantity } => sell_quantities.push(quote_quantity),
+                TradeAction::BuyFraction { fraction } => {
+                    buy_quantities.push(new_balance.base_balance * fraction)
+                }
+                TradeAction::SellFraction { fraction } => {
+                    sell_quantities.push(new_balance.quote_balance * fraction)
+                }
+            }
+        }
+        let net_buys = buy_quantities.len() as i64 - sell_quantities.len() as i64;
+        let majority = self.members.len() as i64 / 2 + 1;
+        let mean = |quantities: &[f64]| quantities.iter().sum::<f64>() / quantities.len() as f64;
+        if net_buys >= majority {
+            return TradeAction::BuyQuote {
+                base_quantity: mean(&buy_quantities),
+            };
+        }
+        if -net_buys >= majority {
+            return TradeAction::SellQuote {
+                quote_quantity: mean(&sell_quantities),
+            };
+        }
+        TradeAction::Pass
+    }
+}
+
+// Which of two series' quote to hold, everything else staying in base. Quote
+// gains base value as its price falls (see Balance), so holding a leg is a
+// bet on its price falling relative to the other's.
+#[derive(Copy, Clone, PartialEq)]
+enum PairPosition {
+    Flat,
+    HoldA,
+    HoldB,
+}
+
+// Two-series counterpart of Strategy, fed the latest trade of both series at
+// every trade of either; returns the position to hold from now on.
+trait PairStrategy {
+    fn react_to_data(&mut self, a: &db::HistoricalTrade, b: &db::HistoricalTrade) -> PairPosition;
+}
+
+// Mean reversion of the log price ratio of a to b. Once its z-score over the
+// last window steps passes entry_z, holds the leg whose price is stretched
+// up (expecting it to come back down); goes flat when |z| falls below exit_z.
+// Quote can't be sold short, so the other leg is left alone rather than shorted.
+struct PairsStrategy {
+    window: usize,
+    entry_z: f64,
+    exit_z: f64,
+    ratios: VecDeque<f64>, // last window log ratios, oldest first
+    sum: f64,
+    sq_sum: f64,
+    position: PairPosition,
+}
+
+impl PairsStrategy {
+    fn new(window: usize, entry_z: f64, exit_z: f64) -> PairsStrategy {
+        PairsStrategy {
+            window,
+            entry_z,
+            exit_z,
+            ratios: VecDeque::with_capacity(window + 1),
+            sum: 0.0,
+            sq_sum: 0.0,
+            position: PairPosition::Flat,
+        }
+    }
+}
+
+impl PairStrategy for PairsStrategy {
+    fn react_to_data(&mut self, a: &db::HistoricalTrade, b: &db::HistoricalTrade) -> PairPosition {
+        let ratio = (a.get_price() / b.get_price()).ln();
+        self.ratios.push_back(ratio);
+        self.sum += ratio;
+        self.sq_sum += ratio * ratio;
+        if self.ratios.len() > self.window {
+            let dropped = self.ratios.pop_front().unwrap();
+            self.sum -= dropped;
+            self.sq_sum -= dropped * dropped;
+        }
+        if self.ratios.len() < self.window {
+            return self.position;
+        }
+        let n = self.ratios.len() as f64;
+        let mean = self.sum / n;
+        let std = f64::max(self.sq_sum / n - mean * mean, 0.0).sqrt();
+        if std == 0.0 {
+            return self.position;
+        }
+        let z = (ratio - mean) / std;
+        self.position = match self.position {
+            PairPosition::Flat if z > self.entry_z => PairPosition::HoldA,
+            PairPosition::Flat if z < -self.entry_z => PairPosition::HoldB,
+            PairPosition::HoldA | PairPosition::HoldB if z.abs() < self.exit_z => {
+                PairPosition::Flat
+            }
+            position => position,
+        };
+        self.position
+    }
+}
+
+struct PairResult {
+    start_base_balance: f64,
+    final_equity: f64, // quote of either leg valued in base at its last price
+    position_changes: usize,
+}
+
+// Runs strategy over the align_by_time join of a and b, starting flat with
+// start_base in base and moving all of it into a leg whenever it asks to.
+fn run_pair_strategy(
+    strategy: &mut dyn PairStrategy,
+    a: &db::Db,
+    b: &db::Db,
+    fee_model: &dyn FeeModel,
+    start_base: f64,
+) -> PairResult {
+    let mut base_balance = start_base;
+    let mut quotes = [0.0, 0.0]; // of a and b
+    let mut last_prices = [0.0, 0.0];
+    let mut position = PairPosition::Flat;
+    let mut position_changes = 0;
+    let leg = |position: PairPosition| match position {
+        PairPosition::HoldA => Some(0),
+        PairPosition::HoldB => Some(1),
+        PairPosition::Flat => None,
+    };
+    for (a_trade, b_trade) in db::align_by_time(a, b) {
+        let (a_trade, b_trade) = match (a_trade, b_trade) {
+            (Some(a_trade), Some(b_trade)) => (a_trade, b_trade),
+            _ => continue,
+        };
+        last_prices = [a_trade.get_price(), b_trade.get_price()];
+        let target = strategy.react_to_data(a_trade, b_trade);
+        if target == position {
+            continue;
+        }
+        if let Some(i) = leg(position) {
+            let mut balance = Balance {
+                base_balance,
+                quote_balance: quotes[i],
+            };
+            balance.sell(quotes[i], fee_model, false, last_prices[i]);
+            base_balance = balance.base_balance;
+            quotes[i] = balance.quote_balance;
+        }
+        if let Some(i) = leg(target) {
+            let mut balance = Balance {
+                base_balance,
+                quote_balance: quotes[i],
+            };
+            balance.buy(base_balance, fee_model, false, last_prices[i]);
+            base_balance = balance.base_balance;
+            quotes[i] = balance.quote_balance;
+        }
+        position = target;
+        position_changes += 1;
+    }
+    let held = |i: usize| {
+        if quotes[i] > 0.0 {
+            quotes[i] / last_prices[i]
+        } else {
+            0.0
+        }
+    };
+    PairResult {
+        start_base_balance: start_base,
+        final_equity: base_balance + held(0) + held(1),
+        position_changes,
+    }
+}
+
+// strategies that can be ensemble members, by their --strategy name
+fn new_strategy(
+    name: &str,
+    balance: Balance,
+    fee: f64,
+    config: &StrategyConfig,
+) -> Option<Box<dyn Strategy>> {
+    match name {
+        "random" => Some(RandomStrategy::new(balance, fee, config)),
+        "momentum" => Some(MomentumStrategy::new(balance, fee, config)),
+        "rsi" => Some(RsiStrategy::new(balance, fee, config)),
+        "trailing-stop" => Some(TrailingStopStrategy::new(balance, fee, config)),
+        "static-avg" => Some(StaticAvgStrategy::new(balance, fee, config)),
+        "vol-adaptive" => Some(VolAdaptiveStrategy::new(balance, fee, config)),
+        "twap" => Some(TwapStrategy::new(balance, fee, config)),
+        _ => None,
+    }
+}
+
+// Builds the strategy called name the way a run would and loads
+// config.resume_state into it, so a bad name or state is reported before the
+// runs start instead of failing partway through them.
+fn check_strategy(name: &str, fee: f64, config: &StrategyConfig) -> Result<(), String> {
+    let balance = Balance {
+        base_balance: 0.0,
+        quote_balance: 0.0,
+    };
+    let mut strategy: Box<dyn Strategy> = match name {
+        "ensemble" => Box::new(EnsembleStrategy {
+            members: EnsembleStrategy::members(balance, fee, config)?,
+        }),
+        _ => new_strategy(name, balance, fee, config)
+            .ok_or_else(|| format!("unknown strategy: {name}"))?,
+    };
+    match &config.resume_state {
+        Some(state) => strategy
+            .load_state(state)
+            .map_err(|e| format!("can't resume {name} from the saved state: {e}")),
+        None => Ok(()),
+    }
+}
+
+// asset names for output, e.g. ETH and BTC for ETHBTC
+struct Assets {
+    base: String,
+    quote: String,
+}
+
+// most common binance quote assets, matched as a suffix of the symbol
+const QUOTE_ASSETS: [&str; 9] = [
+    "USDT", "BUSD", "USDC", "TUSD", "BTC", "ETH", "BNB", "EUR", "TRY",
+];
+
+impl Assets {
+    fn from_symbol(symbol: &str) -> Assets {
+        let symbol = symbol.to_uppercase();
+        for quote in QUOTE_ASSETS {
+            if let Some(base) = symbol.strip_suffix(quote) {
+                if !base.is_empty() {
+                    return Assets {
+                        base: base.to_string(),
+                        quote: quote.to_string(),
+                    };
+                }
+            }
+        }
+        Assets {
+            base: "base".to_string(),
+            quote: "quote".to_string(),
+        }
+    }
+    // e.g. "ETH balance: 1, BTC balance: 0"
+    fn describe(&self, balance: &Balance) -> String {
+        format!(
+            "{} balance: {}, {} balance: {}",
+            self.base, balance.base_balance, self.quote, balance.quote_balance
+        )
+    }
+}
+
+// ETHBTC for data/ETHBTC.json.gz
+fn symbol_from_path(path: &Path) -> String {
+    path.file_name()
+        .map_or("".into(), |name| name.to_string_lossy())
+        .split('.')
+        .next()
+        .unwrap_or("")
+        .to_string()
+}
+
+pub struct Executor {
+    db: db::Db,
+    assets: Assets,
+}
+
+type RangeSimulateFn = fn(
+    &Executor,
+    usize,
+    usize,
+    &ExecutionModel,
+    &StrategyConfig,
+    &SimulationConfig,
+) -> BacktestResult;
+
+// simulate_range for the strategy called name, as accepted by --strategy
+fn range_simulate_fn(name: &str) -> Option<RangeSimulateFn> {
+    match name {
+        "random" => Some(Executor::simulate_range::<RandomStrategy>),
+        "momentum" => Some(Executor::simulate_range::<MomentumStrategy>),
+        "rsi" => Some(Executor::simulate_range::<RsiStrategy>),
+        "trailing-stop" => Some(Executor::simulate_range::<TrailingStopStrategy>),
+        "static-avg" => Some(Executor::simulate_range::<StaticAvgStrategy>),
+        "vol-adaptive" => Some(Executor::simulate_range::<VolAdaptiveStrategy>),
+        "twap" => Some(Executor::simulate_range::<TwapStrategy>),
+        "ensemble" => Some(Executor::simulate_range::<EnsembleStrategy>),
+        _ => None,
+    }
+}
+
+impl Executor {
+    pub fn new<F: AsRef<Path>>(
+        filename: F,
+        symbol: &str,
+        bad_prices: db::BadPricePolicy,
+    ) -> Result<Executor, db::Error> {
+        let mut db = db::Db::new(&filename)?;
+        let skipped = db.check_prices(bad_prices)?;
+        if skipped > 0 {
+            println!(
+                "{:?}: skipped {} trades with bad prices",
+                filename.as_ref(),
+                skipped
+            );
+        }
+        Ok(Executor {
+            db,
+            assets: Assets::from_symbol(symbol),
+        })
+    }
+    // feeds the warmup trades before start_id through consume_data, clamped to the data available
+    fn warm_up(&self, strategy: &mut dyn Strategy, start_id: usize, simulation: &SimulationConfig) {
+        for i in start_id.saturating_sub(simulation.warmup)..start_id {
+            let data = self.db.get_data(i);
+            if simulation.best_match_only && !data.is_best_match {
+                continue;
+            }
+            strategy.consume_data(data);
+        }
+    }
+    // simulate over a range chosen by pick_range
+    fn simulate_picked(
+        &self,
+        simulate: RangeSimulateFn,
+        execution: &ExecutionModel,
+        config: &StrategyConfig,
+        simulation: &SimulationConfig,
+    ) -> Result<BacktestResult, db::Error> {
+        let (start_id, finish_id) = self.pick_range(simulation)?;
+        Ok(simulate(
+            self, start_id, finish_id, execution, config, simulation,
+        ))
+    }
+    // the time window if set, otherwise a random index range, of trade_count
+    // trades if that is set (fewer only when it runs into the end of the data)
+    fn pick_range(&self, simulation: &SimulationConfig) -> Result<(usize, usize), db::Error> {
+        if let Some((start_ms, end_ms)) = simulation.time_window {
+            return self.db.index_range_for_time(start_ms, end_ms);
+        }
+        let mut rng = rand::thread_rng();
+        let start_id: usize = rng.gen_range(0..self.db.get_data_len());
+        if let Some(trade_count) = simulation.trade_count {
+            let finish_id = usize::min(start_id + trade_count, self.db.get_data_len());
+            return Ok((start_id, finish_id));
+        }
+        // finish_id is exclusive, so this keeps at least the trade at start_id
+        let finish_id: usize = rng.gen_range(start_id + 1..=self.db.get_data_len());
+        Ok((start_id, finish_id))
+    }
+    pub fn simulate_range<T: Strategy>(
+        &self,
+        start_id: usize,
+        finish_id: usize,
+        execution: &ExecutionModel,
+        config: &StrategyConfig,
+        simulation: &SimulationConfig,
+    ) -> BacktestResult {
+        let verbose = simulation.verbose;
+        let balance = simulation.opening_balance();
+        // strategies plan around the fee their fills pay
+        let fee_model = execution.fee_model.as_ref();
+        let fee = fee_model.fee_for(&TradeAction::Pass, execution.maker_fills);
+        let mut strategy = T::new(balance, fee, config);
+        if verbose {
+            println!("Generated id: {}-{}", start_id, finish_id);
+        }
+        self.warm_up(strategy.as_mut(), start_id, simulation);
+        if let Some(state) = &config.resume_state {
+            // check_strategy already loaded it once, before any run
+            strategy
+                .load_state(state)
+                .expect("can't resume the strategy from its saved state");
+        }
+        strategy.on_window_start(self.db.get_window(start_id, finish_id));
+        run_strategy_over(
+            strategy.as_mut(),
+            self.db.get_window(start_id, finish_id).iter().rev(),
+            execution,
+            simulation,
+            &self.assets,
+        )
+    }
+}
+
+// Executes action against balance at price; returns "buy" or "sell" with the
+// quantity traded (base for buys, quote for sells), or None for Pass and for
+// actions of zero quantity, which trade nothing and so aren't fills.
+// Shared by everything that turns strategy decisions into balance changes.
+fn apply_action(
+    balance: &mut Balance,
+    action: TradeAction,
+    fee_model: &dyn FeeModel,
+    is_maker: bool,
+    price: f64,
+) -> Option<(&'static str, f64)> {
+    match action.resolved(balance) {
+        TradeAction::Pass
+        | TradeAction::BuyQuote { base_quantity: 0.0 }
+        | TradeAction::SellQuote {
+            quote_quantity: 0.0,
+        } => None,
+        TradeAction::BuyFraction { .. } | TradeAction::SellFraction { .. } => {
+            unreachable!("fractions are resolved above")
+        }
+        TradeAction::SellQuote { quote_quantity } => {
+            if quote_quantity < 0.0 {
+                panic!("CHEETAH!");
+            }
+            balance.sell(quote_quantity, fee_model, is_maker, price);
+            Some(("sell", quote_quantity))
+        }
+        TradeAction::BuyQuote { base_quantity } => {
+            balance.buy(base_quantity, fee_model, is_maker, price);
+            Some(("buy", base_quantity))
+        }
+    }
+}
+
+// Fills action the way execution says: level by level against its order book
+// if it has one, which always takes liquidity and may fill partially, otherwise
+// at price moved by its slippage model. Returns what apply_action does plus
+// the average fill price, or None if nothing was traded.
+fn execute_action(
+    balance: &mut Balance,
+    action: TradeAction,
+    execution: &ExecutionModel,
+    is_maker: bool,
+    price: f64,
+) -> Option<(&'static str, f64, f64)> {
+    let fee_model = execution.fee_model.as_ref();
+    let book = match &execution.order_book {
+        Some(book) => book,
+        None => {
+            let fill_price = execution.slippage_model.fill_price(&action, price);
+            return apply_action(balance, action, fee_model, is_maker, fill_price)
+                .map(|(name, quantity)| (name, quantity, fill_price));
+        }
+    };
+    let (name, fill) = match action.resolved(balance) {
+        TradeAction::Pass => return None,
+        TradeAction::BuyFraction { .. } | TradeAction::SellFraction { .. } => {
+            unreachable!("fractions are resolved above")
+        }
+        TradeAction::BuyQuote { base_quantity } => {
+            ("buy", balance.buy_from_book(base_quantity, fee_model, book))
+        }
+        TradeAction::SellQuote { quote_quantity } => (
+            "sell",
+            balance.sell_from_book(quote_quantity, fee_model, book),
+        ),
+    };
+    if fill.base_quantity <= 0.0 {
+        return None;
+    }
+    let quantity = if name == "buy" {
+        fill.base_quantity
+    } else {
+        fill.quote_quantity
+    };
+    Some((name, quantity, fill.average_price()))
+}
+
+// Feeds trades, oldest first, to an already warmed up strategy and fills its
+// actions. Needs nothing but the trades, so a strategy can be driven by a
+// hand-built vector as well as by a Db window.
+fn run_strategy_over<'a>(
+    strategy: &mut dyn Strategy,
+    trades: impl IntoIterator<Item = &'a db::HistoricalTrade>,
+    execution: &ExecutionModel,
+    simulation: &SimulationConfig,
+    assets: &Assets,
+) -> BacktestResult {
+    let verbose = simulation.verbose;
+    let mut balance = simulation.opening_balance();
+    let mut equity_curve = Vec::new();
+    let mut records = Vec::new();
+    let mut trades_iter = trades.into_iter().enumerate().peekable();
+    let first_data = trades_iter
+        .peek()
+        .map(|(_, data)| *data)
+        .expect("no trades to run the strategy over");
+    let mut last_price = first_data.get_price();
+    let mut last_time_milliseconds = first_data.time_milliseconds;
+    let start_base_balance = balance.base_balance + balance.quote_balance / last_price;
+    // quote in start_balance is taken as bought at the first price
+    let mut pnl = PnlTracker {
+        cost_basis: simulation.start_balance.quote_balance / last_price,
+        realized_pnl: 0.0,
+    };
+    if let Some(position) = simulation.start_position {
+        pnl.on_buy(position.quantity / position.entry_price);
+    }
+    // quote held from the start counts as entered at its average cost
+    let mut excursions = ExcursionTracker {
+        open: None,
+        round_trips: Vec::new(),
+    };
+    if balance.quote_balance > 0.0 {
+        excursions.open = Some((balance.quote_balance / pnl.cost_basis, 0.0, 0.0));
+    }
+    // decisions waiting to fill, with the index of the trade they fill at
+    let mut pending: VecDeque<(usize, TradeAction)> = VecDeque::new();
+    let mut last_fill_id: Option<usize> = None;
+    let mut suppressed_actions = 0;
+    let mut below_min_notional = 0;
+    let mut total_fees = 0.0;
+    let mut over_fee_cap = 0;
+    let mut events = Vec::new();
+    let mut signals = Vec::new();
+    for (i, new_data) in trades_iter {
+        if simulation.best_match_only && !new_data.is_best_match {
+            continue;
+        }
+        let action = strategy.react_to_data(balance, new_data);
+        if simulation.record_events && !matches!(action, TradeAction::Pass) {
+            events.push(Event::Decision {
+                time_milliseconds: new_data.time_milliseconds,
+                action,
+            });
+        }
+        if simulation.record_signals {
+            signals.push(SignalRecord {
+                time_milliseconds: new_data.time_milliseconds,
+                signal: action.side(),
+                indicator: strategy.indicator(),
+            });
+        }
+        if !simulation.signals_only {
+            pending.push_back((i + simulation.fill_delay, action));
+        }
+        last_price = new_data.get_price();
+        last_time_milliseconds = new_data.time_milliseconds;
+        excursions.on_price(last_price);
+        while pending.front().is_some_and(|(fill_id, _)| *fill_id <= i) {
+            let (_, action) = pending.pop_front().unwrap();
+            // capped at fill time, since the balance may have changed since the decision
+            let action = action.capped(&balance, simulation.max_alloc);
+            let in_cooldown =
+                last_fill_id.is_some_and(|last_fill_id| i < last_fill_id + simulation.cooldown);
+            if in_cooldown && !matches!(action, TradeAction::Pass) {
+                suppressed_actions += 1;
+                if simulation.record_events {
+                    events.push(Event::Rejected {
+                        time_milliseconds: new_data.time_milliseconds,
+                        action,
+                        reason: "cooldown",
+                    });
+                }
+                continue;
+            }
+            let too_small = action.notional(last_price) < simulation.min_notional;
+            if too_small && !matches!(action, TradeAction::Pass) {
+                below_min_notional += 1;
+                if simulation.record_events {
+                    events.push(Event::Rejected {
+                        time_milliseconds: new_data.time_milliseconds,
+                        action,
+                        reason: "min_notional",
+                    });
+                }
+                continue;
+            }
+            let fee_capped = simulation
+                .max_total_fees
+                .is_some_and(|max_total_fees| total_fees >= max_total_fees);
+            if fee_capped && !matches!(action, TradeAction::Pass) {
+                over_fee_cap += 1;
+                if simulation.record_events {
+                    events.push(Event::Rejected {
+                        time_milliseconds: new_data.time_milliseconds,
+                        action,
+                        reason: "max_total_fees",
+                    });
+                }
+                continue;
+            }
+            let before = balance;
+            let (name, quantity, fill_price) = match execute_action(
+                &mut balance,
+                action,
+                execution,
+                execution.maker_fills,
+                last_price,
+            ) {
+                Some(fill) => fill,
+                None => continue,
+            };
+            // only actions that traded something start a cooldown
+            last_fill_id = Some(i);
+            // a fee-free fill keeps the balance's value at the fill price
+            let value =
+                |balance: &Balance| balance.base_balance + balance.quote_balance / fill_price;
+            total_fees += value(&before) - value(&balance);
+            // fills stop once the cap is reached, so this warns at most once
+            if let Some(max_total_fees) = simulation.max_total_fees {
+                if total_fees >= max_total_fees {
+                    println!(
+                        "warning: {total_fees} {} paid in fees reached the cap of {max_total_fees}; no further trades this run",
+                        assets.base
+                    );
+                }
+            }
+            if name == "buy" {
+                pnl.on_buy(quantity);
+            } else {
+                pnl.on_sell(
+                    quantity,
+                    before.quote_balance,
+                    balance.base_balance - before.base_balance,
+                );
+            }
+            if verbose {
+                println!(
+                    "{name}! Current price: {last_price}, fill price: {fill_price}, {}",
+                    assets.describe(&balance)
+                );
+            }
+            excursions.on_fill(fill_price, before.quote_balance, balance.quote_balance);
+            if let Some(metrics) = simulation.metrics {
+                metrics.fills.fetch_add(1, Ordering::Relaxed);
+            }
+            if simulation.record_events {
+                events.push(Event::Fill {
+                    time_milliseconds: new_data.time_milliseconds,
+                    action: name,
+                    price: fill_price,
+                    quantity,
+                });
+            }
+            if simulation.record_trades {
+                records.push(TradeRecord {
+                    time_milliseconds: new_data.time_milliseconds,
+                    action: name,
+                    price: fill_price,
+                    quantity,
+                    base_balance_after: balance.base_balance,
+                    quote_balance_after: balance.quote_balance,
+                });
+            }
+        }
+        if simulation.record_equity {
+            equity_curve.push(EquityPoint {
+                time_milliseconds: new_data.time_milliseconds,
+                base_balance: balance.base_balance,
+                quote_balance: balance.quote_balance,
+                mark_price: last_price,
+            });
+        }
+        if let Some(metrics) = simulation.metrics {
+            metrics.observe(&balance, last_price, start_base_balance);
+        }
+    }
+    if verbose {
+        println!("Final bot {}", assets.describe(&balance));
+    }
+    let unrealized_pnl = pnl.unrealized_pnl(balance.quote_balance, last_price);
+    if simulation.final_liquidation && !simulation.signals_only {
+        let liquidation = TradeAction::SellQuote {
+            quote_quantity: balance.quote_balance,
+        };
+        // closing out can't wait for the book to come to it, so it takes
+        let fill = execute_action(&mut balance, liquidation, execution, false, last_price);
+        if let (true, Some((_, quote_quantity, price))) = (simulation.record_events, fill) {
+            events.push(Event::Liquidation {
+                time_milliseconds: last_time_milliseconds,
+                price,
+                quote_quantity,
+            });
+        }
+        excursions.close();
+    }
+    if let Some(metrics) = simulation.metrics {
+        metrics.observe(&balance, last_price, start_base_balance);
+        metrics.runs.fetch_add(1, Ordering::Relaxed);
+    }
+    let final_equity = balance.base_balance + balance.quote_balance / last_price;
+    let time_span_milliseconds = last_time_milliseconds - first_data.time_milliseconds;
+    BacktestResult {
+        start_base_balance,
+        final_equity,
+        equity_curve,
+        trades: records,
+        signals,
+        events,
+        round_trips: excursions.round_trips,
+        time_span_milliseconds,
+        annualized_return: annualized_return(
+            start_base_balance,
+            final_equity,
+            time_span_milliseconds,
+        ),
+        realized_pnl: pnl.realized_pnl,
+        unrealized_pnl,
+        suppressed_actions,
+        below_min_notional,
+        over_fee_cap,
+        final_state: strategy.save_state(),
+    }
+}
+
+// largest fall from a running peak of equity, as a fraction of that peak
+fn max_drawdown(equity_curve: &[EquityPoint]) -> f64 {
+    let mut peak = f64::NEG_INFINITY;
+    let mut drawdown: f64 = 0.0;
+    for point in equity_curve {
+        let equity = point.equity();
+        peak = f64::max(peak, equity);
+        drawdown = f64::max(drawdown, (peak - equity) / peak);
+    }
+    drawdown
+}
+
+// Sharpe of the per-tick equity returns over the last window ticks, at every
+// tick from the window-th on; not annualized. Flat stretches have no defined
+// Sharpe and are skipped.
+fn rolling_sharpe(equity_curve: &[EquityPoint], window: usize) -> Vec<(i64, f64)> {
+    let returns: Vec<f64> = equity_curve
+        .windows(2)
+        .map(|pair| pair[1].equity() / pair[0].equity() - 1.0)
+        .collect();
+    let mut sharpes = Vec::new();
+    if window == 0 {
+        return sharpes;
+    }
+    let mut sum = 0.0;
+    let mut sq_sum = 0.0;
+    for (i, ret) in returns.iter().enumerate() {
+        sum += ret;
+        sq_sum += ret * ret;
+        if i >= window {
+            let dropped = returns[i - window];
+            sum -= dropped;
+            sq_sum -= dropped * dropped;
+        }
+        if i + 1 < window {
+            continue;
+        }
+        let mean = sum / window as f64;
+        let std = f64::max(sq_sum / window as f64 - mean * mean, 0.0).sqrt();
+        if std > 0.0 {
+            // returns[i] ends at equity_curve[i + 1]
+            sharpes.push((equity_curve[i + 1].time_milliseconds, mean / std));
+        }
+    }
+    sharpes
+}
+
+fn save_rolling_sharpe_csv<P: AsRef<Path>>(
+    sharpes: &[(i64, f64)],
+    filename: &P,
+) -> std::io::Result<()> {
+    let file = File::create(filename)?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "time,sharpe")?;
+    for (time_milliseconds, sharpe) in sharpes {
+        writeln!(writer, "{},{}", time_milliseconds, sharpe)?;
+    }
+    writer.flush()
+}
+
+// one line of the fee_sweep table, averaged over its runs
+struct FeeSweepRow {
+    fee_bps: f64,
+    mean_final_equity: f64,
+    mean_start_equity: f64,
+    win_rate: f64,
+}
+
+// the rows fee_sweep prints, one per fee in the order given
+fn fee_sweep_rows(
+    executor: &Executor,
+    simulate: RangeSimulateFn,
+    fees_bps: &[f64],
+    count: i64,
+    slippage_bps: f64,
+    config: &StrategyConfig,
+    simulation: &SimulationConfig,
+) -> Result<Vec<FeeSweepRow>, db::Error> {
+    let ranges = (0..count)
+        .map(|_| executor.pick_range(simulation))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut rows = Vec::new();
+    for &fee_bps in fees_bps {
+        let execution = ExecutionModel {
+            fee_model: Box::new(FlatFee {
+                fee: fee_bps / 10000.0,
+            }),
+            slippage_model: Box::new(FixedBpsSlippage { bps: slippage_bps }),
+            maker_fills: false,
+            order_book: None,
+        };
+        let mut final_sum = 0.0;
+        let mut start_sum = 0.0;
+        let mut wins = 0;
+        for (start_id, finish_id) in &ranges {
+            let result = simulate(
+                executor, *start_id, *finish_id, &execution, config, simulation,
+            );
+            final_sum += result.final_equity;
+            start_sum += result.start_base_balance;
+            if result.final_equity > result.start_base_balance {
+                wins += 1;
+            }
+        }
+        let n = ranges.len() as f64;
+        rows.push(FeeSweepRow {
+            fee_bps,
+            mean_final_equity: final_sum / n,
+            mean_start_equity: start_sum / n,
+            win_rate: wins as f64 / n,
+        });
+    }
+    Ok(rows)
+}
+
+// Runs the strategy at every flat fee in fees_bps over the same count windows
+// and prints the mean final base balance per fee, followed by the fee range
+// in which the mean run stops being profitable.
+fn fee_sweep(
+    executor: &Executor,
+    simulate: RangeSimulateFn,
+    fees_bps: &[f64],
+    count: i64,
+    slippage_bps: f64,
+    config: &StrategyConfig,
+    simulation: &SimulationConfig,
+) -> Result<(), db::Error> {
+    let rows = fee_sweep_rows(
+        executor,
+        simulate,
+        fees_bps,
+        count,
+        slippage_bps,
+        config,
+        simulation,
+    )?;
+    println!("fee bps, mean final base balance, mean start base balance, win rate");
+    let mut last_profitable: Option<f64> = None;
+    let mut first_unprofitable: Option<f64> = None;
+    for row in rows {
+        println!(
+            "{}, {}, {}, {}",
+            row.fee_bps, row.mean_final_equity, row.mean_start_equity, row.win_rate
+        );
+        if row.mean_final_equity > row.mean_start_equity {
+            last_profitable = Some(row.fee_bps);
+        } else if first_unprofitable.is_none() {
+            first_unprofitable = Some(row.fee_bps);
+        }
+    }
+    match (last_profitable, first_unprofitable) {
+        (Some(profitable), Some(unprofitable)) if profitable < unprofitable => println!(
+            "break-even fee between {} and {} bps",
+            profitable, unprofitable
+        ),
+        (Some(_), None) => println!("profitable at every fee swept"),
+        (None, _) => println!("unprofitable at every fee swept"),
+        // the strategy is random or the runs noisy enough that profit isn't monotonic in the fee
+        _ => println!("no single break-even fee"),
+    }
+    Ok(())
+}
+
+// one line of the compare_strategies table, averaged over its runs
+struct ComparisonRow {
+    name: String,
+    mean_final_equity: f64,
+    sharpe: Option<f64>, // of the run returns; None if they're all the same
+    mean_max_drawdown: f64,
+    mean_trade_count: f64,
+    win_rate: f64,
+}
+
+// the rows compare_strategies prints, one per strategy in the order given
+fn comparison_rows(
+    executor: &Executor,
+    strategies: &[(String, RangeSimulateFn)],
+    count: i64,
+    execution: &ExecutionModel,
+    config: &StrategyConfig,
+    simulation: &SimulationConfig,
+) -> Result<Vec<ComparisonRow>, db::Error> {
+    let ranges = (0..count)
+        .map(|_| executor.pick_range(simulation))
+        .collect::<Result<Vec<_>, _>>()?;
+    let recording = SimulationConfig {
+        record_equity: true,
+        record_trades: true,
+        ..*simulation
+    };
+    let mut rows = Vec::new();
+    for (name, simulate) in strategies {
+        let results: Vec<BacktestResult> = ranges
+            .iter()
+            .map(|(start_id, finish_id)| {
+                simulate(
+                    executor, *start_id, *finish_id, execution, config, &recording,
+                )
+            })
+            .collect();
+        let n = results.len() as f64;
+        let mean = |values: &mut dyn Iterator<Item = f64>| values.sum::<f64>() / n;
+        let returns: Vec<f64> = results
+            .iter()
+            .map(|result| result.final_equity / result.start_base_balance - 1.0)
+            .collect();
+        let mean_return = mean(&mut returns.iter().cloned());
+        let return_std = mean(&mut returns.iter().map(|r| (r - mean_return).powi(2))).sqrt();
+        let wins = results
+            .iter()
+            .filter(|result| result.final_equity > result.start_base_balance)
+            .count();
+        rows.push(ComparisonRow {
+            name: name.clone(),
+            mean_final_equity: mean(&mut results.iter().map(|result| result.final_equity)),
+            // like rolling_sharpe, no sharpe without any spread in the returns
+            sharpe: if return_std > 0.0 {
+                Some(mean_return / return_std)
+            } else {
+                None
+            },
+            mean_max_drawdown: mean(
+                &mut results
+                    .iter()
+                    .map(|result| max_drawdown(&result.equity_curve)),
+            ),
+            mean_trade_count: mean(&mut results.iter().map(|result| result.trades.len() as f64)),
+            win_rate: wins as f64 / n,
+        });
+    }
+    Ok(rows)
+}
+
+// Runs every strategy over the same count windows and prints one row of
+// metrics per strategy. Sharpe is taken over the per-run returns, not over
+// time, so it is comparable between strategies but not to annualized figures.
+fn compare_strategies(
+    executor: &Executor,
+    strategies: &[(String, RangeSimulateFn)],
+    count: i64,
+    execution: &ExecutionModel,
+    config: &StrategyConfig,
+    simulation: &SimulationConfig,
+) -> Result<(), db::Error> {
+    let rows = comparison_rows(executor, strategies, count, execution, config, simulation)?;
+    println!("strategy, mean final equity, sharpe, mean max drawdown, mean trade count, win rate");
+    for row in rows {
+        let sharpe = match row.sharpe {
+            Some(sharpe) => sharpe.to_string(),
+            None => "n/a".to_string(),
+        };
+        println!(
+            "{}, {}, {}, {}, {}, {}",
+            row.name,
+            row.mean_final_equity,
+            sharpe,
+            row.mean_max_drawdown,
+            row.mean_trade_count,
+            row.win_rate
+        );
+    }
+    Ok(())
+}
+
+// linear interpolation between closest ranks; sorted must be ascending and non-empty
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+// z for a two-sided 95% interval
+const Z_95: f64 = 1.96;
+
+// Wilson score interval for a success rate, which unlike the normal
+// approximation stays within 0..1 and is usable for few runs or rates near 0 or 1
+fn wilson_interval(successes: usize, total: usize, z: f64) -> Option<(f64, f64)> {
+    if total == 0 {
+        return None;
+    }
+    let n = total as f64;
+    let p = successes as f64 / n;
+    let denominator = 1.0 + z * z / n;
+    let center = (p + z * z / (2.0 * n)) / denominator;
+    let margin = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+    Some((center - margin, center + margin))
+}
+
+struct RunStats {
+    mean: f64,
+    median: f64,
+    p5: f64,
+    p25: f64,
+    p75: f64,
+    p95: f64,
+    worst: f64,
+    best: f64,
+}
+
+impl RunStats {
+    fn new(values: &[f64]) -> Option<RunStats> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(RunStats {
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            median: percentile(&sorted, 50.0),
+            p5: percentile(&sorted, 5.0),
+            p25: percentile(&sorted, 25.0),
+            p75: percentile(&sorted, 75.0),
+            p95: percentile(&sorted, 95.0),
+            worst: sorted[0],
+            best: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+// State of the runs so far, for --metrics-port. Updated from the simulation
+// loop on every trade, so a long run can be watched while it goes.
+#[derive(Default)]
+pub struct Metrics {
+    runs: AtomicU64,          // finished
+    fills: AtomicU64,         // buys and sells, the final liquidations not included
+    last_price: AtomicU64,    // f64 bits, as are the rest
+    base_balance: AtomicU64,  // of the current run
+    quote_balance: AtomicU64, // of the current run
+    pnl: AtomicU64,           // of the current run, in base at the last price
+}
+
+fn load_f64(value: &AtomicU64) -> f64 {
+    f64::from_bits(value.load(Ordering::Relaxed))
+}
+
+fn store_f64(value: &AtomicU64, x: f64) {
+    value.store(x.to_bits(), Ordering::Relaxed);
+}
+
+impl Metrics {
+    fn observe(&self, balance: &Balance, price: f64, start_base_balance: f64) {
+        let equity = balance.base_balance + balance.quote_balance / price;
+        store_f64(&self.last_price, price);
+        store_f64(&self.base_balance, balance.base_balance);
+        store_f64(&self.quote_balance, balance.quote_balance);
+        store_f64(&self.pnl, equity - start_base_balance);
+    }
+    // Prometheus text exposition format
+    fn render(&self) -> String {
+        let metrics = [
+            (
+                "runs_total",
+                "counter",
+                "Finished runs",
+                self.runs.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "fills_total",
+                "counter",
+                "Buys and sells filled",
+                self.fills.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "last_price",
+                "gauge",
+                "Price of the last trade seen",
+                load_f64(&self.last_price),
+            ),
+            (
+                "base_balance",
+                "gauge",
+                "Base balance of the current run",
+                load_f64(&self.base_balance),
+            ),
+            (
+                "quote_balance",
+                "gauge",
+                "Quote balance of the current run",
+                load_f64(&self.quote_balance),
+            ),
+            (
+                "pnl",
+                "gauge",
+                "Profit of the current run so far, in base",
+                load_f64(&self.pnl),
+            ),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let name = format!("hist_executor_{name}");
+            text += &format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+        }
+        text
+    }
+}
+
+// Answers every request, whatever its path, with the metrics until the process
+// exits. Returns the address listened on, which has a free port for port 0.
+fn serve_metrics(host: &str, port: u16, metrics: &'static Metrics) -> std::io::Result<SocketAddr> {
+    let listener = TcpListener::bind((host, port))?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    let make_service = make_service_fn(move |_connection| async move {
+        Ok::<_, Infallible>(service_fn(move |_request| async move {
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(metrics.render()))
+        }))
+    });
+    let server = {
+        let _runtime = runtime.enter();
+        Server::from_tcp(listener)
+            .map_err(std::io::Error::other)?
+            .serve(make_service)
+    };
+    thread::spawn(move || {
+        if let Err(e) = runtime.block_on(server) {
+            println!("metrics server stopped: {e}");
+        }
+    });
+    Ok(addr)
+}
+
+// outcomes of the runs on one file, or on all of them
+#[derive(Default)]
+struct RunSummary {
+    success_count: usize,
+    draw_count: usize,
+    total_count: usize,
+    suppressed_actions: usize,
+    below_min_notional: usize,
+    over_fee_cap: usize,
+    final_balances: Vec<f64>,
+    annualized_returns: Vec<f64>,
+    realized_pnls: Vec<f64>,
+    unrealized_pnls: Vec<f64>,
+    favorable_excursions: Vec<f64>, // one per round trip
+    adverse_excursions: Vec<f64>,
+}
+
+impl RunSummary {
+    fn add(&mut self, result: &BacktestResult) {
+        self.final_balances.push(result.final_equity);
+        self.realized_pnls.push(result.realized_pnl);
+        self.unrealized_pnls.push(result.unrealized_pnl);
+        self.suppressed_actions += result.suppressed_actions;
+        self.below_min_notional += result.below_min_notional;
+        self.over_fee_cap += result.over_fee_cap;
+        for round_trip in &result.round_trips {
+            self.favorable_excursions
+                .push(round_trip.max_favorable_excursion);
+            self.adverse_excursions
+                .push(round_trip.max_adverse_excursion);
+        }
+        match result.annualized_return {
+            Some(annualized_return) => self.annualized_returns.push(annualized_return),
+            None => println!(
+                "window of {} ms is too short to annualize; skipping its annualized return",
+                result.time_span_milliseconds
+            ),
+        }
+        self.total_count += 1;
+        if result.final_equity > result.start_base_balance {
+            self.success_count += 1;
+        } else if result.final_equity == result.start_base_balance {
+            self.draw_count += 1;
+        }
+    }
+    fn extend(&mut self, other: &RunSummary) {
+        self.success_count += other.success_count;
+        self.draw_count += other.draw_count;
+        self.total_count += other.total_count;
+        self.suppressed_actions += other.suppressed_actions;
+        self.below_min_notional += other.below_min_notional;
+        self.over_fee_cap += other.over_fee_cap;
+        self.final_balances.extend(&other.final_balances);
+        self.annualized_returns.extend(&other.annualized_returns);
+        self.realized_pnls.extend(&other.realized_pnls);
+        self.unrealized_pnls.extend(&other.unrealized_pnls);
+        self.favorable_excursions
+            .extend(&other.favorable_excursions);
+        self.adverse_excursions.extend(&other.adverse_excursions);
+    }
+    fn print(&self, histogram_buckets: usize) {
+        println!(
+            "success count: {}, draw_count: {}, total_count: {}",
+            self.success_count, self.draw_count, self.total_count
+        );
+        if let Some((low, high)) = wilson_interval(self.success_count, self.total_count, Z_95) {
+            println!(
+                "win rate: {}, 95% confidence interval {} - {}",
+                self.success_count as f64 / self.total_count as f64,
+                low,
+                high
+            );
+        }
+        if self.suppressed_actions > 0 {
+            println!(
+                "actions suppressed by cooldown: {}",
+                self.suppressed_actions
+            );
+        }
+        if self.below_min_notional > 0 {
+            println!(
+                "actions below the minimum notional: {}",
+                self.below_min_notional
+            );
+        }
+        if self.over_fee_cap > 0 {
+            println!("actions dropped over the fee cap: {}", self.over_fee_cap);
+        }
+        if let Some(stats) = RunStats::new(&self.final_balances) {
+            println!(
+                "final base balance: mean {}, median {}, p5 {}, p25 {}, p75 {}, p95 {}, worst {}, best {}",
+                stats.mean,
+                stats.median,
+                stats.p5,
+                stats.p25,
+                stats.p75,
+                stats.p95,
+                stats.worst,
+                stats.best
+            );
+        }
+        if histogram_buckets > 0 {
+            println!("final base balance distribution:");
+            print_histogram(&self.final_balances, histogram_buckets);
+        }
+        if let (Some(realized), Some(unrealized)) = (
+            RunStats::new(&self.realized_pnls),
+            RunStats::new(&self.unrealized_pnls),
+        ) {
+            println!(
+                "pnl in base before final liquidation: realized mean {}, median {}; unrealized mean {}, median {}",
+                realized.mean, realized.median, unrealized.mean, unrealized.median
+            );
+        }
+        if let (Some(favorable), Some(adverse)) = (
+            RunStats::new(&self.favorable_excursions),
+            RunStats::new(&self.adverse_excursions),
+        ) {
+            println!(
+                "round trips: {}; max favorable excursion mean {}, median {}, p95 {}; max adverse excursion mean {}, median {}, p5 {}",
+                self.favorable_excursions.len(),
+                favorable.mean,
+                favorable.median,
+                favorable.p95,
+                adverse.mean,
+                adverse.median,
+                adverse.p5
+            );
+        }
+        if let Some(stats) = RunStats::new(&self.annualized_returns) {
+            println!(
+                "annualized return: mean {}, median {}, p5 {}, p95 {}, worst {}, best {}",
+                stats.mean, stats.median, stats.p5, stats.p95, stats.worst, stats.best
+            );
+        }
+    }
+}
+
+// (lower, upper, count) for equal-width buckets spanning min to max of values
+fn histogram(values: &[f64], buckets: usize) -> Vec<(f64, f64, usize)> {
+    if values.is_empty() || buckets == 0 {
+        return Vec::new();
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let width = (max - min) / buckets as f64;
+    let mut counts = vec![0; buckets];
+    for value in values {
+        let bucket = if width > 0.0 {
+            ((value - min) / width) as usize
+        } else {
+            0
+        };
+        // max lands exactly on the upper edge of the last bucket
+        counts[usize::min(bucket, buckets - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + width * i as f64, min + width * (i + 1) as f64, count))
+        .collect()
+}
+
+const HISTOGRAM_WIDTH: usize = 50;
+
+fn print_histogram(values: &[f64], buckets: usize) {
+    let bins = histogram(values, buckets);
+    let max_count = bins.iter().map(|(_, _, count)| *count).max().unwrap_or(0);
+    for (lower, upper, count) in bins {
+        let bar = "#".repeat(count * HISTOGRAM_WIDTH / usize::max(max_count, 1));
+        println!("{:>12.6} - {:<12.6} {:>6} {}", lower, upper, count, bar);
+    }
+}
+
+fn bad_price_policy(skip_bad_prices: bool) -> db::BadPricePolicy {
+    if skip_bad_prices {
+        db::BadPricePolicy::Skip
+    } else {
+        db::BadPricePolicy::Reject
+    }
+}
+
+// Everything a recorded run depends on, saved with --manifest-out to rerun it
+// with --replay. Only pick_range draws from thread_rng, and the range it picked
+// is kept as start_id and finish_id, so every strategy replays exactly.
+#[derive(Serialize, Deserialize)]
+struct RunManifest {
+    input: PathBuf,
+    symbol: String,
+    strategy: String,
+    start_id: usize,
+    finish_id: usize,
+    skip_bad_prices: bool, // trades skipped on load shift the ids
+    fees: FeeSettings,
+    slippage_bps: f64,
+    config: StrategyConfig,
+    simulation: SimulationConfig,
+}
+
+impl RunManifest {
+    fn save<P: AsRef<Path>>(&self, filename: &P) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()
+    }
+    fn load<P: AsRef<Path>>(filename: &P) -> Result<RunManifest, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        serde_json::from_str(&text).map_err(|e| e.to_string())
+    }
+}
+
+// reads a strategy state written by --state-out; the null saved for a
+// strategy without state resumes nothing
+fn load_state_json<P: AsRef<Path>>(filename: &P) -> Result<Option<serde_json::Value>, String> {
+    let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+// reruns the run described by the manifest at filename
+fn replay<P: AsRef<Path>>(filename: &P) -> Result<BacktestResult, String> {
+    let manifest = RunManifest::load(filename)?;
+    let simulate = range_simulate_fn(&manifest.strategy)
+        .ok_or_else(|| format!("unknown strategy: {}", manifest.strategy))?;
+    let execution = ExecutionModel {
+        fee_model: manifest.fees.fee_model(),
+        slippage_model: Box::new(FixedBpsSlippage {
+            bps: manifest.slippage_bps,
+        }),
+        maker_fills: manifest.fees.maker_fills,
+        order_book: None,
+    };
+    let fee = execution
+        .fee_model
+        .fee_for(&TradeAction::Pass, execution.maker_fills);
+    check_strategy(&manifest.strategy, fee, &manifest.config)?;
+    let bad_prices = bad_price_policy(manifest.skip_bad_prices);
+    let executor =
+        Executor::new(&manifest.input, &manifest.symbol, bad_prices).map_err(|e| e.to_string())?;
+    if manifest.start_id >= manifest.finish_id || manifest.finish_id > executor.db.get_data_len() {
+        return Err(format!(
+            "range {}-{} doesn't fit the {} trades of {:?}",
+            manifest.start_id,
+            manifest.finish_id,
+            executor.db.get_data_len(),
+            manifest.input
+        ));
+    }
+    Ok(simulate(
+        &executor,
+        manifest.start_id,
+        manifest.finish_id,
+        &execution,
+        &manifest.config,
+        &manifest.simulation,
+    ))
+}
+
+// defaults read from --config; any flag given on the command line wins.
+// Unknown keys are errors, so a misspelt one isn't silently ignored.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    count: Option<i64>,
+    fee: Option<f64>,
+    slippage_bps: Option<f64>,
+    warmup: Option<usize>,
+    strategy: Option<String>,
+    symbol: Option<String>,
+}
+
+impl FileConfig {
+    fn load<P: AsRef<Path>>(filename: &P) -> Result<FileConfig, String> {
+        let text = std::fs::read_to_string(filename).map_err(|e| e.to_string())?;
+        toml::from_str(&text).map_err(|e| e.to_string())
+    }
+    // fills in the flags that weren't given on the command line
+    fn apply_to(self, opt: &mut Opt) {
+        opt.count = opt.count.or(self.count);
+        opt.fee = opt.fee.or(self.fee);
+        opt.slippage_bps = opt.slippage_bps.or(self.slippage_bps);
+        opt.warmup = opt.warmup.or(self.warmup);
+        opt.strategy = opt.strategy.take().or(self.strategy);
+        opt.symbol = opt.symbol.take().or(self.symbol);
+    }
+}
+
+// --trades; a run needs at least one trade
+fn parse_trade_count(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(trade_count) => Ok(trade_count),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "example", about = "An example of StructOpt usage.")]
+struct Opt {
+    #[structopt(
+        short = "i",
+        long = "input",
+        parse(from_os_str),
+        required_unless = "replay"
+    )]
+    input: Vec<PathBuf>, // each file is backtested on its own, then all are summarized together
+    // precedence: command line flag, then --config file, then the default noted here
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+    // rerun the run saved by --manifest-out; every other flag is ignored
+    #[structopt(long = "replay", parse(from_os_str))]
+    replay: Option<PathBuf>,
+    #[structopt(long = "manifest-out", parse(from_os_str))]
+    manifest_out: Option<PathBuf>, // inputs of the first run, as json, for --replay
+    #[structopt(long = "symbol")]
+    symbol: Option<String>, // only used to label balances in output; default from file name, e.g. ETHBTC.json
+    #[structopt(long = "skip-bad-prices")]
+    skip_bad_prices: bool, // drop trades with an unparseable or non-positive price instead of failing
+    #[structopt(short = "v", long = "verbose")]
+    verbose: bool, // print every fill of every run
+    #[structopt(short = "c", long = "count")]
+    count: Option<i64>, // required, either here or in --config
+    #[structopt(short = "f", long = "fee")]
+    fee: Option<f64>, // default 0.001
+    #[structopt(long = "maker-fee", allow_hyphen_values = true)]
+    maker_fee: Option<f64>, // defaults to --fee when only --taker-fee is given; negative for a rebate
+    #[structopt(long = "taker-fee")]
+    taker_fee: Option<f64>, // defaults to --fee when only --maker-fee is given
+    #[structopt(long = "maker-fills")]
+    maker_fills: bool, // fill orders as resting limit orders at the maker fee; takers otherwise
+    #[structopt(long = "buy-fee-bps")]
+    buy_fee_bps: Option<f64>, // defaults to --fee when only --sell-fee-bps is given
+    #[structopt(long = "sell-fee-bps")]
+    sell_fee_bps: Option<f64>, // defaults to --fee when only --buy-fee-bps is given
+    #[structopt(long = "fee-discount", default_value = "0.0")]
+    fee_discount: f64,
+    #[structopt(long = "slippage-bps")]
+    slippage_bps: Option<f64>, // default 0.0
+    #[structopt(long = "equity-out", parse(from_os_str))]
+    equity_out: Option<PathBuf>, // equity curve of the first run, as csv
+    #[structopt(long = "plot-out", parse(from_os_str))]
+    plot_out: Option<PathBuf>, // price and equity of the first run, as svg
+    #[structopt(long = "rolling-sharpe-out", parse(from_os_str))]
+    rolling_sharpe_out: Option<PathBuf>, // rolling sharpe of the first run, as csv
+    #[structopt(long = "rolling-sharpe-window", default_value = "1000")]
+    rolling_sharpe_window: usize, // in ticks
+    #[structopt(long = "trades-out", parse(from_os_str))]
+    trades_out: Option<PathBuf>, // every buy/sell of the first run, as csv
+    #[structopt(long = "events-out", parse(from_os_str))]
+    events_out: Option<PathBuf>, // every decision, fill and rejection of the first run, as json
+    #[structopt(long = "signals-out", parse(from_os_str))]
+    signals_out: Option<PathBuf>, // every decision of the first run and its indicator, as csv
+    #[structopt(long = "signals-only")]
+    signals_only: bool, // record decisions without filling them; balances stay as they started
+    // strategy state to start every run from, as written by --state-out
+    #[structopt(long = "state-in", parse(from_os_str))]
+    state_in: Option<PathBuf>,
+    #[structopt(long = "state-out", parse(from_os_str))]
+    state_out: Option<PathBuf>, // strategy state at the end of the first run, as json
+    // milliseconds since epoch or a UTC datetime like 2022-05-15T11:32:27Z
+    #[structopt(long = "start-ms", parse(try_from_str = db::parse_time))]
+    start_ms: Option<i64>, // backtest only trades at or after this time
+    #[structopt(long = "end-ms", parse(try_from_str = db::parse_time))]
+    end_ms: Option<i64>, // backtest only trades before this time
+    // run this many trades from a random start, so runs are comparable in length
+    #[structopt(
+        long = "trades",
+        conflicts_with_all = &["start-ms", "end-ms"],
+        parse(try_from_str = parse_trade_count)
+    )]
+    trades: Option<usize>,
+    #[structopt(long = "best-match-only")]
+    best_match_only: bool, // ignore trades not flagged is_best_match
+    #[structopt(long = "metrics-port")]
+    metrics_port: Option<u16>, // serve metrics for prometheus on this port while running
+    #[structopt(long = "metrics-host", default_value = "127.0.0.1")]
+    metrics_host: String, // 0.0.0.0 to be scraped from other machines
+    #[structopt(long = "max-alloc", default_value = "1.0")]
+    max_alloc: f64,
+    #[structopt(long = "fill-delay", default_value = "0")]
+    fill_delay: usize, // in trades; decisions still pending at the end of the window never fill
+    #[structopt(long = "histogram-buckets", default_value = "10")]
+    histogram_buckets: usize, // of final base balances across runs; 0 to skip the histogram
+    #[structopt(long = "no-final-liquidation")]
+    no_final_liquidation: bool, // keep the end position and report it marked at the last price
+    #[structopt(long = "cooldown", default_value = "0")]
+    cooldown: usize, // trades after a fill during which buys and sells are ignored
+    #[structopt(long = "min-notional", default_value = "0.0")]
+    min_notional: f64, // in quote; smaller buys and sells are ignored, the final liquidation isn't
+    #[structopt(long = "max-total-fees")]
+    max_total_fees: Option<f64>, // in base, per run; stop trading once this much was paid in fees
+    #[structopt(long = "start-base", default_value = "1.0")]
+    start_base: f64,
+    #[structopt(long = "start-quote", default_value = "0.0")]
+    start_quote: f64,
+    // an open position to start with, e.g. quote bought earlier at a different price
+    #[structopt(
+        long = "start-position-quantity",
+        requires = "start-position-entry-price"
+    )]
+    start_position_quantity: Option<f64>,
+    #[structopt(
+        long = "start-position-entry-price",
+        requires = "start-position-quantity"
+    )]
+    start_position_entry_price: Option<f64>,
+    #[structopt(short = "w", long = "warmup")]
+    warmup: Option<usize>, // default 0
+    #[structopt(short = "s", long = "strategy")]
+    strategy: Option<String>, // default random
+    #[structopt(long = "momentum-lookback", default_value = "100")]
+    momentum_lookback: usize,
+    #[structopt(long = "momentum-entry-threshold", default_value = "0.001")]
+    momentum_entry_threshold: f64,
+    #[structopt(
+        long = "momentum-exit-threshold",
+        default_value = "0.0",
+        allow_hyphen_values = true
+    )]
+    momentum_exit_threshold: f64,
+    #[structopt(long = "rsi-period", default_value = "14")]
+    rsi_period: usize,
+    #[structopt(long = "rsi-oversold", default_value = "30")]
+    rsi_oversold: f64,
+    #[structopt(long = "rsi-overbought", default_value = "70")]
+    rsi_overbought: f64,
+    #[structopt(long = "trailing-stop-pct", default_value = "0.01")]
+    trailing_stop_pct: f64,
+    #[structopt(long = "static-avg-threshold-pct", default_value = "0.01")]
+    static_avg_threshold_pct: f64,
+    #[structopt(long = "vol-window", default_value = "100")]
+    vol_window: usize, // trades, for both the momentum and the volatility estimate
+    #[structopt(long = "vol-base-threshold", default_value = "0.0005")]
+    vol_base_threshold: f64,
+    #[structopt(long = "vol-sensitivity", default_value = "10")]
+    vol_sensitivity: f64, // threshold added per unit of per-trade return std
+    // run the pairs strategy once over the first --input and this file, both
+    // in full, instead of the usual runs
+    #[structopt(long = "pair-with", parse(from_os_str))]
+    pair_with: Option<PathBuf>,
+    #[structopt(long = "pairs-window", default_value = "1000")]
+    pairs_window: usize, // steps of the aligned series the ratio's mean and std are taken over
+    #[structopt(long = "pairs-entry-z", default_value = "2.0")]
+    pairs_entry_z: f64,
+    #[structopt(long = "pairs-exit-z", default_value = "0.5")]
+    pairs_exit_z: f64,
+    #[structopt(
+        long = "ensemble-members",
+        default_value = "momentum,rsi,trailing-stop",
+        use_delimiter = true
+    )]
+    ensemble_members: Vec<String>,
+    // run each of these strategies over the same windows and print a table of
+    // metrics instead of the usual summary
+    #[structopt(long = "compare", use_delimiter = true)]
+    compare: Vec<String>,
+    // run the strategy at each of these flat fees, in bps, over the same windows
+    // and print the mean final base balance per fee instead of the usual summary
+    #[structopt(long = "fee-sweep", use_delimiter = true)]
+    fee_sweep: Vec<f64>,
+}
+
+pub fn run() {
+    let mut opt = Opt::from_args();
+    if let Some(path) = &opt.replay {
+        match replay(path) {
+            Ok(result) => println!(
+                "final base balance: {}, realized pnl: {}, unrealized pnl: {}",
+                result.final_equity, result.realized_pnl, result.unrealized_pnl
+            ),
+            Err(e) => {
+                println!("could not replay {:?}: {}", path, e);
+                ::std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = &opt.config {
+        match FileConfig::load(path) {
+            Ok(file_config) => file_config.apply_to(&mut opt),
+            Err(e) => {
+                println!("could not read config file {:?}: {}", path, e);
+                ::std::process::exit(1);
+            }
+        }
+    }
+    let count = match opt.count {
+        Some(count) => count,
+        None => {
+            println!("--count is required, either as a flag or in --config");
+            ::std::process::exit(1);
+        }
+    };
+    let fee = opt.fee.unwrap_or(0.001);
+    let slippage_bps = opt.slippage_bps.unwrap_or(0.0);
+    let warmup = opt.warmup.unwrap_or(0);
+    let strategy = opt.strategy.clone().unwrap_or_else(|| "random".to_string());
+    let resume_state = match &opt.state_in {
+        None => None,
+        Some(path) => match load_state_json(path) {
+            Ok(state) => state,
+            Err(e) => {
+                println!("could not read strategy state from {:?}: {}", path, e);
+                ::std::process::exit(1);
+            }
+        },
+    };
+    let config = StrategyConfig {
+        momentum_lookback: opt.momentum_lookback,
+        momentum_entry_threshold: opt.momentum_entry_threshold,
+        momentum_exit_threshold: opt.momentum_exit_threshold,
+        rsi_period: opt.rsi_period,
+        rsi_oversold: opt.rsi_oversold,
+        rsi_overbought: opt.rsi_overbought,
+        trailing_stop_pct: opt.trailing_stop_pct,
+        static_avg_threshold_pct: opt.static_avg_threshold_pct,
+        vol_window: opt.vol_window,
+        vol_base_threshold: opt.vol_base_threshold,
+        vol_sensitivity: opt.vol_sensitivity,
+        ensemble_members: opt.ensemble_members.clone(),
+        resume_state,
+    };
+    for name in std::iter::once(&strategy).chain(&opt.compare) {
+        if let Err(e) = check_strategy(name, fee, &config) {
+            println!("{e}");
+            ::std::process::exit(1);
+        }
+    }
+    if opt.buy_fee_bps.is_none() && opt.sell_fee_bps.is_none() {
+        if opt
+            .maker_fee
+            .is_some_and(|maker_fee| maker_fee < -MAX_MAKER_REBATE)
+        {
+            println!("--maker-fee rebate can't be more than {MAX_MAKER_REBATE}");
+            ::std::process::exit(1);
+        }
+        if opt.taker_fee.is_some_and(|taker_fee| taker_fee < 0.0) {
+            println!("--taker-fee can't be negative, only maker fills get rebates");
+            ::std::process::exit(1);
+        }
+    }
+    let fees = FeeSettings {
+        fee,
+        maker_fee: opt.maker_fee,
+        taker_fee: opt.taker_fee,
+        buy_fee_bps: opt.buy_fee_bps,
+        sell_fee_bps: opt.sell_fee_bps,
+        discount: opt.fee_discount,
+        maker_fills: opt.maker_fills,
+    };
+    let time_window = match (opt.start_ms, opt.end_ms) {
+        (None, None) => None,
+        (start_ms, end_ms) => Some((start_ms.unwrap_or(i64::MIN), end_ms.unwrap_or(i64::MAX))),
+    };
+    let metrics: Option<&'static Metrics> = match opt.metrics_port {
+        None => None,
+        Some(port) => {
+            // lives until the process exits, as does the server reading it
+            let metrics = Box::leak(Box::<Metrics>::default());
+            match serve_metrics(&opt.metrics_host, port, metrics) {
+                Ok(addr) => println!("serving metrics on http://{addr}/metrics"),
+                Err(e) => {
+                    println!(
+                        "could not serve metrics on {}:{}: {}",
+                        opt.metrics_host, port, e
+                    );
+                    ::std::process::exit(1);
+                }
+            }
+            Some(metrics)
+        }
+    };
+    let simulation = SimulationConfig {
+        start_balance: Balance {
+            base_balance: opt.start_base,
+            quote_balance: opt.start_quote,
+        },
+        start_position: opt
+            .start_position_quantity
+            .zip(opt.start_position_entry_price)
+            .map(|(quantity, entry_price)| Position {
+                quantity,
+                entry_price,
+            }),
+        time_window,
+        trade_count: opt.trades,
+        max_alloc: opt.max_alloc,
+        fill_delay: opt.fill_delay,
+        cooldown: opt.cooldown,
+        min_notional: opt.min_notional,
+        max_total_fees: opt.max_total_fees,
+        final_liquidation: !opt.no_final_liquidation,
+        warmup,
+        record_equity: false,
+        record_trades: false,
+        record_events: false,
+        record_signals: false,
+        signals_only: opt.signals_only,
+        best_match_only: opt.best_match_only,
+        metrics,
+        verbose: opt.verbose,
+    };
+    let execution = ExecutionModel {
+        fee_model: fees.fee_model(),
+        slippage_model: Box::new(FixedBpsSlippage { bps: slippage_bps }),
+        maker_fills: fees.maker_fills,
+        order_book: None,
+    };
+    let bad_prices = bad_price_policy(opt.skip_bad_prices);
+    let simulate = match range_simulate_fn(&strategy) {
+        Some(simulate) => simulate,
+        None => {
+            println!("unknown strategy: {strategy}");
+            ::std::process::exit(1);
+        }
+    };
+    if let Some(pair_with) = &opt.pair_with {
+        let a = &opt.input[0];
+        let loaded = Executor::new(a, &symbol_from_path(a), bad_prices).and_then(|a_executor| {
+            let b_executor = Executor::new(pair_with, &symbol_from_path(pair_with), bad_prices)?;
+            Ok((a_executor, b_executor))
+        });
+        let (a_executor, b_executor) = match loaded {
+            Ok(executors) => executors,
+            Err(e) => {
+                println!("could not open pair inputs: {}", e);
+                ::std::process::exit(1);
+            }
+        };
+        let mut strategy =
+            PairsStrategy::new(opt.pairs_window, opt.pairs_entry_z, opt.pairs_exit_z);
+        let result = run_pair_strategy(
+            &mut strategy,
+            &a_executor.db,
+            &b_executor.db,
+            execution.fee_model.as_ref(),
+            opt.start_base,
+        );
+        println!(
+            "pairs {:?} / {:?}: start base balance {}, final base balance {}, position changes {}",
+            a, pair_with, result.start_base_balance, result.final_equity, result.position_changes
+        );
+        return;
+    }
+    let mut comparison = Vec::new();
+    for name in &opt.compare {
+        match range_simulate_fn(name) {
+            Some(simulate) => comparison.push((name.clone(), simulate)),
+            None => {
+                println!("unknown strategy to compare: {name}");
+                ::std::process::exit(1);
+            }
+        }
+    }
+    let mut aggregate = RunSummary::default();
+    let mut per_file = Vec::new();
+    for (file_id, input) in opt.input.iter().enumerate() {
+        let symbol = opt
+            .symbol
+            .clone()
+            .unwrap_or_else(|| symbol_from_path(input));
+        let executor = match Executor::new(input, &symbol, bad_prices) {
+            Ok(executor) => executor,
+            Err(e) => {
+                println!("could not open input file {:?}: {}; skipping", input, e);
+                continue;
+            }
+        };
+        println!("{:?}: db data len: {}", input, executor.db.get_data_len());
+        if !comparison.is_empty() {
+            let compared = compare_strategies(
+                &executor,
+                &comparison,
+                count,
+                &execution,
+                &config,
+                &simulation,
+            );
+            if let Err(e) = compared {
+                println!(
+                    "could not compare strategies on {:?}: {}; skipping",
+                    input, e
+                );
+            }
+            continue;
+        }
+        if !opt.fee_sweep.is_empty() {
+            let swept = fee_sweep(
+                &executor,
+                simulate,
+                &opt.fee_sweep,
+                count,
+                slippage_bps,
+                &config,
+                &simulation,
+            );
+            if let Err(e) = swept {
+                println!("could not sweep fees on {:?}: {}; skipping", input, e);
+            }
+            continue;
+        }
+        let mut summary = RunSummary::default();
+        for i in 0..count {
+            let result = if file_id == 0 && i == 0 {
+                // only the first run on the first file is recorded
+                let recording = SimulationConfig {
+                    record_equity: opt.equity_out.is_some()
+                        || opt.plot_out.is_some()
+                        || opt.rolling_sharpe_out.is_some(),
+                    record_trades: opt.trades_out.is_some(),
+                    record_events: opt.events_out.is_some(),
+                    record_signals: opt.signals_out.is_some(),
+                    ..simulation
+                };
+                let range = executor.pick_range(&recording);
+                if let (Ok((start_id, finish_id)), Some(manifest_out)) = (&range, &opt.manifest_out)
+                {
+                    let manifest = RunManifest {
+                        input: input.clone(),
+                        symbol: symbol.clone(),
+                        strategy: strategy.clone(),
+                        start_id: *start_id,
+                        finish_id: *finish_id,
+                        skip_bad_prices: opt.skip_bad_prices,
+                        fees: fees.clone(),
+                        slippage_bps,
+                        config: config.clone(),
+                        simulation,
+                    };
+                    if let Err(e) = manifest.save(manifest_out) {
+                        println!("could not write manifest to {:?}: {}", manifest_out, e);
+                    }
+                }
+                let result = range.map(|(start_id, finish_id)| {
+                    simulate(
+                        &executor, start_id, finish_id, &execution, &config, &recording,
+                    )
+                });
+                if let (Ok(result), Some(_)) = (&result, &opt.manifest_out) {
+                    // to check a --replay against
+                    println!(
+                        "recorded run final base balance: {}, realized pnl: {}, unrealized pnl: {}",
+                        result.final_equity, result.realized_pnl, result.unrealized_pnl
+                    );
+                }
+                if let (Ok(result), Some(equity_out)) = (&result, &opt.equity_out) {
+                    if let Err(e) = result.save_equity_csv(equity_out) {
+                        println!("could not write equity curve to {:?}: {}", equity_out, e);
+                    }
+                }
+                if let (Ok(result), Some(sharpe_out)) = (&result, &opt.rolling_sharpe_out) {
+                    let sharpes = rolling_sharpe(&result.equity_curve, opt.rolling_sharpe_window);
+                    if let Err(e) = save_rolling_sharpe_csv(&sharpes, sharpe_out) {
+                        println!("could not write rolling sharpe to {:?}: {}", sharpe_out, e);
+                    }
+                }
+                if let (Ok(result), Some(plot_out)) = (&result, &opt.plot_out) {
+                    if let Err(e) = result.save_plot_svg(plot_out) {
+                        println!("could not write plot to {:?}: {}", plot_out, e);
+                    }
+                }
+                if let (Ok(result), Some(trades_out)) = (&result, &opt.trades_out) {
+                    if let Err(e) = result.save_trades_csv(trades_out) {
+                        println!("could not write trade log to {:?}: {}", trades_out, e);
+                    }
+                }
+                if let (Ok(result), Some(events_out)) = (&result, &opt.events_out) {
+                    if let Err(e) = result.save_events_json(events_out) {
+                        println!("could not write events to {:?}: {}", events_out, e);
+                    }
+                }
+                if let (Ok(result), Some(signals_out)) = (&result, &opt.signals_out) {
+                    if let Err(e) = result.save_signals_csv(signals_out) {
+                        println!("could not write signals to {:?}: {}", signals_out, e);
+                    }
+                }
+                if let (Ok(result), Some(state_out)) = (&result, &opt.state_out) {
+                    if let Err(e) = result.save_state_json(state_out) {
+                        println!("could not write strategy state to {:?}: {}", state_out, e);
+                    }
+                }
+                result
+            } else {
+                executor.simulate_picked(simulate, &execution, &config, &simulation)
+            };
+            match result {
+                Ok(result) => summary.add(&result),
+                Err(e) => {
+                    println!("could not run simulation on {:?}: {}; skipping", input, e);
+                    break;
+                }
+            }
+        }
+        println!("{:?}:", input);
+        summary.print(opt.histogram_buckets);
+        aggregate.extend(&summary);
+        per_file.push((symbol, summary));
+    }
+    if opt.input.len() > 1 {
+        println!(
+            "symbol, runs, successes, draws, mean final base balance, median final base balance"
+        );
+        for (symbol, summary) in &per_file {
+            if let Some(stats) = RunStats::new(&summary.final_balances) {
+                println!(
+                    "{}, {}, {}, {}, {}, {}",
+                    symbol,
+                    summary.total_count,
+                    summary.success_count,
+                    summary.draw_count,
+                    stats.mean,
+                    stats.median
+                );
+            }
+        }
+        println!("all files:");
+        aggregate.print(opt.histogram_buckets);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trades(prices: &[f64]) -> Vec<db::HistoricalTrade> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| db::HistoricalTrade {
+                trade_id: i as i64,
+                price: price.to_string(),
+                quantity: "1.0".to_string(),
+                quote_quantity: price.to_string(),
+                time_milliseconds: 1_650_000_000_000 + i as i64 * 1000,
+                is_buyer_maker: i % 2 == 0,
+                is_best_match: true,
+            })
+            .collect()
+    }
+
+    fn strategy_config() -> StrategyConfig {
+        StrategyConfig {
+            momentum_lookback: 3,
+            momentum_entry_threshold: 0.01,
+            momentum_exit_threshold: 0.0,
+            rsi_period: 3,
+            rsi_oversold: 30.0,
+            rsi_overbought: 70.0,
+            trailing_stop_pct: 1.0,
+            static_avg_threshold_pct: 1.0,
+            vol_window: 3,
+            vol_base_threshold: 0.01,
+            vol_sensitivity: 1.0,
+            ensemble_members: Vec::new(),
+            resume_state: None,
+        }
+    }
+
+    fn sides(mut strategy: Box<dyn Strategy>, prices: &[f64]) -> Vec<&'static str> {
+        let balance = Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        };
+        trades(prices)
+            .iter()
+            .map(|trade| match strategy.react_to_data(balance, trade) {
+                TradeAction::Pass => "pass",
+                TradeAction::BuyQuote { .. } | TradeAction::BuyFraction { .. } => "buy",
+                TradeAction::SellQuote { .. } | TradeAction::SellFraction { .. } => "sell",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn momentum_enters_on_the_way_up_and_exits_on_the_way_down() {
+        // lookback 3, entry above +1%, exit below 0%
+        let prices = [1.0, 1.0, 1.0, 1.0, 1.1, 1.2, 1.3, 1.2, 1.1, 1.0];
+        let balance = Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        };
+        let strategy = MomentumStrategy::new(balance, 0.0, &strategy_config());
+        let sides = sides(strategy, &prices);
+        // 1.1 / 1.0 is the first momentum over the entry, 1.1 / 1.2 the first below the exit
+        assert_eq!(sides.iter().position(|side| *side == "buy"), Some(4));
+        assert_eq!(sides.iter().position(|side| *side == "sell"), Some(8));
+        assert_eq!(sides.iter().filter(|side| **side != "pass").count(), 2);
+    }
+
+    #[test]
+    fn missing_input_file_is_an_error() {
+        let missing = Executor::new("no/such/file.json", "ETHBTC", db::BadPricePolicy::Reject);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn maker_taker_fee_picks_the_side_and_applies_the_discount() {
+        let fees = MakerTakerFee {
+            maker_fee: 0.001,
+            taker_fee: 0.002,
+            discount: 0.25,
+        };
+        let action = TradeAction::Pass;
+        assert!((fees.fee_for(&action, true) - 0.00075).abs() < 1e-12);
+        assert!((fees.fee_for(&action, false) - 0.0015).abs() < 1e-12);
+        // a fill that takes liquidity pays the taker fee
+        let mut balance = Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        };
+        balance.buy(1.0, &fees, false, 0.05);
+        assert!((balance.quote_balance - 0.05 * (1.0 - 0.0015)).abs() < 1e-12);
+        let flat = FlatFee { fee: 0.001 };
+        assert_eq!(flat.fee_for(&action, true), flat.fee_for(&action, false));
+    }
+
+    #[test]
+    fn maker_fills_pay_the_maker_fee() {
+        let run = |maker_fills| {
+            let execution = ExecutionModel {
+                fee_model: Box::new(MakerTakerFee {
+                    maker_fee: 0.0,
+                    taker_fee: 0.001,
+                    discount: 0.0,
+                }),
+                slippage_model: Box::new(FixedBpsSlippage { bps: 0.0 }),
+                maker_fills,
+                order_book: None,
+            };
+            let simulation = SimulationConfig {
+                final_liquidation: false,
+                ..simulation()
+            };
+            let actions = vec![TradeAction::BuyQuote { base_quantity: 1.0 }];
+            run_scripted(actions, &[0.05; 3], &execution, &simulation)
+        };
+        assert!((run(true).final_equity - 1.0).abs() < 1e-12);
+        assert!((run(false).final_equity - 0.999).abs() < 1e-12);
+    }
+
+    #[test]
+    fn slippage_reduces_realized_pnl() {
+        // buy with the whole base balance and sell it all back at the same last price
+        let round_trip = |slippage: &dyn SlippageModel| {
+            let fees = FlatFee { fee: 0.0 };
+            let mut balance = Balance {
+                base_balance: 1.0,
+                quote_balance: 0.0,
+            };
+            let buy = TradeAction::BuyQuote { base_quantity: 1.0 };
+            balance.buy(1.0, &fees, false, slippage.fill_price(&buy, 0.05));
+            let quote_quantity = balance.quote_balance;
+            let sell = TradeAction::SellQuote { quote_quantity };
+            balance.sell(
+                quote_quantity,
+                &fees,
+                false,
+                slippage.fill_price(&sell, 0.05),
+            );
+            balance.base_balance
+        };
+        let exact = round_trip(&FixedBpsSlippage { bps: 0.0 });
+        let penalized = round_trip(&FixedBpsSlippage { bps: 10.0 });
+        // buying and selling back at the same price without fees is free
+        assert!((exact - 1.0).abs() < 1e-12);
+        assert!((penalized - 0.999 / 1.001).abs() < 1e-12);
+    }
+
+    fn execution(fee: f64) -> ExecutionModel {
+        ExecutionModel {
+            fee_model: Box::new(FlatFee { fee }),
+            slippage_model: Box::new(FixedBpsSlippage { bps: 0.0 }),
+            maker_fills: false,
+            order_book: None,
+        }
+    }
+
+    fn simulation() -> SimulationConfig {
+        SimulationConfig {
+            start_balance: Balance {
+                base_balance: 1.0,
+                quote_balance: 0.0,
+            },
+            start_position: None,
+            time_window: None,
+            trade_count: None,
+            max_alloc: 1.0,
+            fill_delay: 0,
+            cooldown: 0,
+            min_notional: 0.0,
+            max_total_fees: None,
+            final_liquidation: true,
+            warmup: 0,
+            record_equity: false,
+            record_trades: false,
+            record_events: false,
+            record_signals: false,
+            signals_only: false,
+            best_match_only: false,
+            metrics: None,
+            verbose: false,
+        }
+    }
+
+    fn executor(prices: &[f64]) -> Executor {
+        let mut trades = trades(prices);
+        trades.reverse(); // the db keeps the newest trade first
+        Executor {
+            db: db::Db::from(trades).unwrap(),
+            assets: Assets::from_symbol("ETHBTC"),
+        }
+    }
+
+    #[test]
+    fn warm_up_feeds_the_trades_just_before_the_start() {
+        let executor = executor(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let config = StrategyConfig {
+            momentum_lookback: 10,
+            ..strategy_config()
+        };
+        let balance = Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        };
+        let mut strategy = MomentumStrategy {
+            balance,
+            prices: VecDeque::new(),
+            lookback: config.momentum_lookback,
+            entry_threshold: config.momentum_entry_threshold,
+            exit_threshold: config.momentum_exit_threshold,
+            in_position: false,
+        };
+        let mut simulation = simulation();
+        simulation.warmup = 2;
+        executor.warm_up(&mut strategy, 4, &simulation);
+        assert_eq!(strategy.prices, [3.0, 4.0]);
+        // a warm-up longer than the history before the start takes what there is
+        strategy.prices.clear();
+        simulation.warmup = 100;
+        executor.warm_up(&mut strategy, 2, &simulation);
+        assert_eq!(strategy.prices, [1.0, 2.0]);
+    }
+
+    #[test]
+    fn equity_curve_csv_has_a_row_per_point() {
+        let point = |time_milliseconds, base_balance, quote_balance, mark_price| EquityPoint {
+            time_milliseconds,
+            base_balance,
+            quote_balance,
+            mark_price,
+        };
+        let result = BacktestResult {
+            start_base_balance: 1.0,
+            final_equity: 3.0,
+            equity_curve: vec![point(1000, 1.0, 0.0, 0.05), point(2000, 0.5, 0.1, 0.04)],
+            trades: Vec::new(),
+            signals: Vec::new(),
+            events: Vec::new(),
+            round_trips: Vec::new(),
+            time_span_milliseconds: 1000,
+            annualized_return: None,
+            realized_pnl: 0.0,
+            unrealized_pnl: 0.0,
+            suppressed_actions: 0,
+            below_min_notional: 0,
+            over_fee_cap: 0,
+            final_state: None,
+        };
+        // half the base plus 0.1 quote at 0.04 quote per base
+        assert!((result.equity_curve[1].equity() - 3.0).abs() < 1e-12);
+        let path = std::env::temp_dir().join(format!("equity_{}.csv", std::process::id()));
+        result.save_equity_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "time,base_balance,quote_balance,mark_price,equity");
+        assert_eq!(rows[1], "1000,1,0,0.05,1");
+        assert_eq!(rows[2], "2000,0.5,0.1,0.04,3");
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn portfolio_rotates_between_pairs_sharing_a_quote() {
+        let fees = FlatFee { fee: 0.001 };
+        let mut portfolio = Portfolio::new();
+        portfolio.deposit("ETH", 2.0);
+        // ETHBTC: give up one ETH for BTC, then BNBBTC: spend all that BTC on BNB
+        portfolio.buy("ETH", "BTC", 1.0, &fees, 0.05);
+        let btc = portfolio.get("BTC");
+        assert!((btc - 0.05 * 0.999).abs() < 1e-12);
+        portfolio.sell("BNB", "BTC", btc, &fees, 0.01);
+        assert_eq!(portfolio.get("ETH"), 1.0);
+        assert!(portfolio.get("BTC").abs() < 1e-12);
+        assert!((portfolio.get("BNB") - 5.0 * 0.999 * 0.999).abs() < 1e-12);
+        assert_eq!(portfolio.get("USDT"), 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "BTC balance < 0!")]
+    fn portfolio_rejects_spending_more_than_it_holds() {
+        let mut portfolio = Portfolio::new();
+        portfolio.deposit("BTC", 0.01);
+        portfolio.sell("ETH", "BTC", 0.02, &FlatFee { fee: 0.0 }, 0.05);
+    }
+
+    #[test]
+    fn rsi_matches_a_published_reference() {
+        // closes and 14-period RSI of the StockCharts worked example, which
+        // rounds its intermediate averages, hence the tolerance
+        let closes = [
+            44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03,
+            45.61, 46.28, 46.28, 46.00, 46.03, 46.41, 46.22, 45.64, 46.21, 46.25, 45.71, 46.45,
+            45.78, 45.35, 44.03, 44.18, 44.22, 44.57, 43.42, 42.66, 43.13,
+        ];
+        let reference = [
+            70.53, 66.32, 66.55, 69.41, 66.36, 57.97, 62.93, 63.26, 56.06, 62.38, 54.71, 50.42,
+            39.99, 41.46, 41.87, 45.46, 37.30, 33.08, 37.77,
+        ];
+        let mut strategy = RsiStrategy {
+            balance: Balance {
+                base_balance: 1.0,
+                quote_balance: 0.0,
+            },
+            period: 14,
+            oversold: 30.0,
+            overbought: 70.0,
+            last_price: None,
+            changes_seen: 0,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+            rsi: None,
+            prev_rsi: None,
+            in_position: false,
+        };
+        let mut values = Vec::new();
+        for close in closes {
+            strategy.update_rsi(close);
+            values.extend(strategy.rsi);
+        }
+        assert_eq!(values.len(), reference.len());
+        for (value, expected) in values.iter().zip(reference) {
+            assert!((value - expected).abs() < 0.1, "{value} vs {expected}");
+        }
+    }
+
+    // two levels a side around 0.05, 1 base at the best price and 2 behind it
+    fn shallow_book() -> OrderBook {
+        let level = |price, quantity| OrderBookLevel { price, quantity };
+        OrderBook {
+            bids: vec![level(0.0499, 1.0), level(0.0498, 2.0)],
+            asks: vec![level(0.0501, 1.0), level(0.0502, 2.0)],
+        }
+    }
+
+    #[test]
+    fn order_book_fills_walk_the_levels_and_stop_when_dry() {
+        let fees = FlatFee { fee: 0.0 };
+        let mut balance = Balance {
+            base_balance: 5.0,
+            quote_balance: 0.0,
+        };
+        // 2 base take the whole best bid and half the next level
+        let fill = balance.buy_from_book(2.0, &fees, &shallow_book());
+        assert_eq!(fill.base_quantity, 2.0);
+        let vwap = (0.0499 + 0.0498) / 2.0;
+        assert!((fill.average_price() - vwap).abs() < 1e-12);
+        assert!((balance.quote_balance - 2.0 * vwap).abs() < 1e-12);
+        assert!(vwap < 0.0499);
+        // the bids only hold 3 base, so a 5 base order fills partially
+        let mut balance = Balance {
+            base_balance: 5.0,
+            quote_balance: 0.0,
+        };
+        let fill = balance.buy_from_book(5.0, &fees, &shallow_book());
+        assert_eq!(fill.base_quantity, 3.0);
+        assert_eq!(balance.base_balance, 2.0);
+        // spending quote on the asks pays more than the best ask on average
+        let quote = balance.quote_balance;
+        let fill = balance.sell_from_book(quote, &fees, &shallow_book());
+        assert!((fill.quote_quantity - quote).abs() < 1e-12);
+        assert!((balance.base_balance - 2.0 - fill.base_quantity).abs() < 1e-12);
+        assert!(fill.average_price() > 0.0501);
+    }
+
+    #[test]
+    fn runs_with_an_order_book_fill_at_its_average_price() {
+        let mut execution = execution(0.0);
+        execution.order_book = Some(shallow_book());
+        let mut simulation = simulation();
+        simulation.start_balance.base_balance = 5.0;
+        simulation.record_trades = true;
+        simulation.final_liquidation = false;
+        let buy = |base_quantity| TradeAction::BuyQuote { base_quantity };
+        // the last price is the top of the book, but 2 base reach the second level
+        let result = run_scripted(vec![buy(2.0)], &[0.0499], &execution, &simulation);
+        let fill = &result.trades[0];
+        assert_eq!(fill.quantity, 2.0);
+        assert!(fill.price < 0.0499);
+        assert!((fill.price - (0.0499 + 0.0498) / 2.0).abs() < 1e-12);
+        // 4 base more than the bids hold: only 3 are filled and recorded
+        let result = run_scripted(vec![buy(4.0)], &[0.0499], &execution, &simulation);
+        assert_eq!(result.trades[0].quantity, 3.0);
+        assert_eq!(result.trades[0].base_balance_after, 2.0);
+        // an empty side of the book trades nothing, so nothing is recorded
+        execution.order_book = Some(OrderBook {
+            bids: Vec::new(),
+            asks: Vec::new(),
+        });
+        let result = run_scripted(vec![buy(1.0)], &[0.0499], &execution, &simulation);
+        assert!(result.trades.is_empty());
+    }
+
+    #[test]
+    fn trailing_stop_sells_at_the_retracement_from_the_high() {
+        let mut config = strategy_config();
+        config.trailing_stop_pct = 0.1;
+        // the high is 1.2, so the stop sits at 1.08; 1.21 is a new high and re-enters
+        let prices = [1.0, 1.1, 1.2, 1.1, 1.09, 1.07, 1.15, 1.21];
+        let balance = Balance {
+            base_balance: 1.0,
+            quote_balance: 0.0,
+        };
+        let sides = sides(TrailingStopStrategy::new(balance, 0.0, &config), &prices);
+        assert_eq!(
+            sides,
+            vec!["buy", "pass", "pass", "pass", "pass", "sell", "pass", "buy"]
+        );
+    }
+
+    #[test]
+    fn percentiles_interpolate_between_ranks() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 50.0), 3.0);
+        assert_eq!(percentile(&sorted, 100.0), 5.0);
+        // rank 0.2 of the way from 1.0 to 2.0
+        assert!((percentile(&sorted, 5.0) - 1.2).abs() < 1e-12);
+        assert_eq!(percentile(&[7.0], 95.0), 7.0);
+        let stats = RunStats::new(&[4.0, 1.0, 3.0, 2.0]).unwrap();
+        assert_eq!((stats.worst, stats.best, stats.mean), (1.0, 4.0, 2.5));
+        assert_eq!(stats.median, 2.5);
+        assert_eq!((stats.p25, stats.p75), (1.75, 3.25));
+        assert!(RunStats::new(&[]).is_none());
+    }
+
+    #[test]
+    fn outcomes_compare_against_the_configured_start() {
+        let mut simulation = simulation();
+        simulation.start_balance = Balance {
+            base_balance: 0.0,
+            quote_balance: 0.1,
+        };
+        // 0.1 quote is worth 2 base at 0.05, and the price never moves
+        let result = executor(&[0.05; 10])
+            .simulate_picked(
+                Executor::simulate_range::<RandomStrategy>,
+                &execution(0.0),
+                &strategy_config(),
+                &simulation,
+            )
+            .unwrap();
+        assert!((result.start_base_balance - 2.0).abs() < 1e-12);
+        // so ending on 2 base is a draw, not a win over the old fixed start of 1
+        assert!((result.final_equity - result.start_base_balance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn time_windows_select_the_trades_inside_them() {
+        let executor = executor(&[0.05; 10]);
+        let t0 = 1_650_000_000_000;
+        let mut simulation = simulation();
+        // end is exclusive: trades at t0 + 2s, 3s and 4s
+        simulation.time_window = Some((t0 + 2000, t0 + 5000));
+        assert_eq!(
+            executor
+                .db
+                .index_range_for_time(t0 + 2000, t0 + 5000)
+                .unwrap(),
+            (2, 5)
+        );
+        simulation.record_equity = true;
+        let result = executor
+            .simulate_picked(
+                Executor::simulate_range::<RandomStrategy>,
+                &execution(0.0),
+                &strategy_config(),
+                &simulation,
+            )
+            .unwrap();
+        let times: Vec<i64> = result
+            .equity_curve
+            .iter()
+            .map(|point| point.time_milliseconds - t0)
+            .collect();
+        assert_eq!(times, vec![2000, 3000, 4000]);
+        // empty, reversed and out of data windows are errors
+        for window in [
+            (t0 + 2000, t0 + 2000),
+            (t0 + 5000, t0 + 2000),
+            (t0 + 20_000, t0 + 30_000),
+        ] {
+            let (start_ms, end_ms) = window;
+            let range = executor.db.index_range_for_time(start_ms, end_ms);
+            assert!(range.is_err(), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let path =
+            std::env::temp_dir().join(format!("hist_executor_{}_config.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "count = 50\nfee = 0.002\nstrategy = \"rsi\"\nsymbol = \"BNBBTC\"\n",
+        )
+        .unwrap();
+        let file_config = FileConfig::load(&path).unwrap();
+        let mut opt = Opt::from_iter(["hist_executor", "-i", "ethbtc.json", "--fee", "0.0005"]);
+        file_config.apply_to(&mut opt);
+        assert_eq!(opt.fee, Some(0.0005));
+        assert_eq!(opt.count, Some(50));
+        assert_eq!(opt.strategy.as_deref(), Some("rsi"));
+        assert_eq!(opt.symbol.as_deref(), Some("BNBBTC"));
+        // neither given: run falls back to its own default
+        assert_eq!(opt.slippage_bps, None);
+        // a misspelt key is an error rather than a silently ignored default
+        std::fs::write(&path, "slipage_bps = 5.0\n").unwrap();
+        let error = FileConfig::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains("slipage_bps"), "{error}");
+    }
+
+    #[test]
+    fn get_window_holds_the_trading_window_newest_first() {
+        let executor = executor(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let window = executor.db.get_window(1, 4);
+        let prices: Vec<f64> = window.iter().map(|trade| trade.get_price()).collect();
+        assert_eq!(prices, [4.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn twap_spreads_the_base_balance_over_the_window() {
+        let mut simulation = simulation();
+        simulation.warmup = 1;
+        simulation.record_trades = true;
+        simulation.final_liquidation = false;
+        // trades 1 to 4 are the window; trade 0 only warms up and isn't counted
+        let result = executor(&[1.0, 2.0, 4.0, 2.0, 1.0, 3.0]).simulate_range::<TwapStrategy>(
+            1,
+            5,
+            &execution(0.0),
+            &strategy_config(),
+            &simulation,
+        );
+        let spent: Vec<f64> = result.trades.iter().map(|trade| trade.quantity).collect();
+        assert_eq!(spent.len(), 4);
+        assert!(spent.iter().all(|quantity| (quantity - 0.25).abs() < 1e-12));
+        assert!(result.trades[3].base_balance_after.abs() < 1e-12);
+        // a quarter of the base at each of 2, 4, 2 and 1
+        let quote = 0.25 * (2.0 + 4.0 + 2.0 + 1.0);
+        assert!((result.trades[3].quote_balance_after - quote).abs() < 1e-12);
+        // without a window it doesn't know how to split, so it doesn't trade
+        let mut strategy =
+            new_strategy("twap", simulation.start_balance, 0.0, &strategy_config()).unwrap();
+        let action = strategy.react_to_data(simulation.start_balance, &trades(&[1.0])[0]);
+        assert!(matches!(action, TradeAction::Pass));
+    }
+
+    #[test]
+    fn static_avg_buys_below_and_sells_above_the_warmup_average() {
+        let mut config = strategy_config();
+        config.static_avg_threshold_pct = 0.05;
+        let mut strategy = StaticAvgStrategy::new(simulation().start_balance, 0.0, &config);
+        for trade in trades(&[0.9, 1.1, 1.0, 1.0]) {
+            strategy.consume_data(&trade);
+        }
+        // the average is 1.0: buy under 0.95, then sell over 1.05, once
+        let prices = [1.0, 0.97, 0.94, 1.0, 1.04, 1.06, 0.9, 1.1];
+        assert_eq!(
+            sides(strategy, &prices),
+            ["pass", "pass", "buy", "pass", "pass", "sell", "pass", "pass"]
+        );
+    }
+
+    #[test]
+    fn oversized_actions_are_clamped_to_max_alloc() {
+        let balance = Balance {
+            base_balance: 2.0,
+            quote_balance: 0.1,
+        };
+        match (TradeAction::BuyQuote { base_quantity: 5.0 }).capped(&balance, 0.25) {
+            TradeAction::BuyQuote { base_quantity } => assert_eq!(base_quantity, 0.5),
+            _ => panic!("a capped buy is still a buy"),
+        }
+        let sell = TradeAction::SellQuote {
+            quote_quantity: 5.0,
+        };
+        match sell.capped(&balance, 0.25) {
+            TradeAction::SellQuote { quote_quantity } => assert_eq!(quote_quantity, 0.025),
+            _ => panic!("a capped sell is still a sell"),
+        }
+        // quantities already under the cap are left alone
+        match (TradeAction::BuyQuote { base_quantity: 0.1 }).capped(&balance, 0.25) {
+            TradeAction::BuyQuote { base_quantity } => assert_eq!(base_quantity, 0.1),
+            _ => panic!("a capped buy is still a buy"),
+        }
+    }
+
+    // buys quote with half a base on the first trade it sees, then passes
+    struct BuyOnFirstTrade {
+        done: bool,
+    }
+
+    impl Strategy for BuyOnFirstTrade {
+        fn new(_balance: Balance, _fee: f64, _config: &StrategyConfig) -> Box<dyn Strategy> {
+            Box::new(BuyOnFirstTrade { done: false })
+        }
+        fn react_to_data(&mut self, _: Balance, _: &db::HistoricalTrade) -> TradeAction {
+            if self.done {
+                return TradeAction::Pass;
+            }
+            self.done = true;
+            TradeAction::BuyQuote { base_quantity: 0.5 }
+        }
+        fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {}
+    }
+
+    #[test]
+    fn delayed_fills_take_the_later_price() {
+        let executor = executor(&[0.05, 0.051, 0.052, 0.053, 0.054]);
+        let mut simulation = simulation();
+        simulation.record_equity = true;
+        let quote_held = |simulation: &SimulationConfig| -> Vec<f64> {
+            executor
+                .simulate_range::<BuyOnFirstTrade>(
+                    0,
+                    5,
+                    &execution(0.0),
+                    &strategy_config(),
+                    simulation,
+                )
+                .equity_curve
+                .iter()
+                .map(|point| point.quote_balance)
+                .collect()
+        };
+        // decided on the first trade either way
+        let immediate = quote_held(&simulation);
+        assert!((immediate[0] - 0.5 * 0.05).abs() < 1e-12);
+        simulation.fill_delay = 2;
+        let delayed = quote_held(&simulation);
+        assert_eq!(delayed[..2], [0.0, 0.0]);
+        assert!((delayed[2] - 0.5 * 0.052).abs() < 1e-12);
+    }
+
+    // replays a fixed list of actions, then passes
+    struct ScriptedStrategy {
+        actions: VecDeque<TradeAction>,
+    }
+
+    impl Strategy for ScriptedStrategy {
+        fn new(_balance: Balance, _fee: f64, _config: &StrategyConfig) -> Box<dyn Strategy> {
+            Box::new(ScriptedStrategy {
+                actions: VecDeque::new(),
+            })
+        }
+        fn react_to_data(&mut self, _: Balance, _: &db::HistoricalTrade) -> TradeAction {
+            self.actions.pop_front().unwrap_or(TradeAction::Pass)
+        }
+        fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {}
+    }
+
+    fn run_scripted(
+        actions: Vec<TradeAction>,
+        prices: &[f64],
+        execution: &ExecutionModel,
+        simulation: &SimulationConfig,
+    ) -> BacktestResult {
+        let mut strategy = ScriptedStrategy {
+            actions: actions.into(),
+        };
+        run_strategy_over(
+            &mut strategy,
+            &trades(prices),
+            execution,
+            simulation,
+            &Assets::from_symbol("ETHBTC"),
+        )
+    }
+
+    #[test]
+    fn ensemble_nets_out_member_votes() {
+        let buy = |base_quantity| TradeAction::BuyQuote { base_quantity };
+        let sell = |quote_quantity| TradeAction::SellQuote { quote_quantity };
+        let mut ensemble = EnsembleStrategy {
+            members: vec![
+                Box::new(ScriptedStrategy {
+                    actions: vec![buy(0.25), buy(0.2), sell(1.0), TradeAction::Pass].into(),
+                }),
+                Box::new(ScriptedStrategy {
+                    actions: vec![buy(0.75), sell(2.0), sell(3.0), buy(0.4)].into(),
+                }),
+            ],
+        };
+        let balance = simulation().start_balance;
+        let data = &trades(&[0.05])[0];
+        // (side, quantity) per tick
+        let votes: Vec<(&str, f64)> = (0..4)
+            .map(|_| match ensemble.react_to_data(balance, data) {
+                TradeAction::Pass => ("pass", 0.0),
+                TradeAction::BuyQuote { base_quantity } => ("buy", base_quantity),
+                TradeAction::SellQuote { quote_quantity } => ("sell", quote_quantity),
+                _ => panic!("members only vote absolute quantities"),
+            })
+            .collect();
+        // both buy: the mean quantity; a buy and a sell cancel out; both sell;
+        // one of two isn't a majority
+        assert_eq!(
+            votes,
+            vec![("buy", 0.5), ("pass", 0.0), ("sell", 2.0), ("pass", 0.0)]
+        );
+    }
+
+    #[test]
+    fn unknown_ensemble_members_are_rejected() {
+        let mut config = strategy_config();
+        config.ensemble_members = vec!["momentum".to_string(), "rsi".to_string()];
+        assert!(check_strategy("ensemble", 0.001, &config).is_ok());
+        config.ensemble_members.push("nonexistent".to_string());
+        assert!(check_strategy("ensemble", 0.001, &config).is_err());
+        assert!(check_strategy("nonexistent", 0.001, &config).is_err());
+    }
+
+    #[test]
+    fn bad_resume_states_are_rejected_up_front() {
+        let mut config = strategy_config();
+        config.resume_state = Some(serde_json::json!({ "prices": "not a list" }));
+        assert!(check_strategy("momentum", 0.001, &config).is_err());
+        config.ensemble_members = vec!["momentum".to_string()];
+        config.resume_state = Some(serde_json::json!([null, null]));
+        assert!(check_strategy("ensemble", 0.001, &config).is_err());
+    }
+
+    // buys on the first tick, sells on the third and buys again on the fifth
+    struct BuySellBuy {
+        tick: usize,
+    }
+
+    impl Strategy for BuySellBuy {
+        fn new(_balance: Balance, _fee: f64, _config: &StrategyConfig) -> Box<dyn Strategy> {
+            Box::new(BuySellBuy { tick: 0 })
+        }
+        fn react_to_data(&mut self, _: Balance, _: &db::HistoricalTrade) -> TradeAction {
+            self.tick += 1;
+            match self.tick {
+                1 => TradeAction::BuyQuote { base_quantity: 0.5 },
+                3 => TradeAction::SellQuote {
+                    quote_quantity: 0.01,
+                },
+                5 => TradeAction::BuyQuote { base_quantity: 0.1 },
+                _ => TradeAction::Pass,
+            }
+        }
+        fn consume_data(&mut self, _new_data: &db::HistoricalTrade) {}
+    }
+
+    #[test]
+    fn trade_log_has_a_row_per_fill() {
+        let mut simulation = simulation();
+        simulation.record_trades = true;
+        let result = executor(&[0.05; 6]).simulate_range::<BuySellBuy>(
+            0,
+            6,
+            &execution(0.001),
+            &strategy_config(),
+            &simulation,
+        );
+        let path =
+            std::env::temp_dir().join(format!("hist_executor_{}_trades.csv", std::process::id()));
+        result.save_trades_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        // a header, then one row per non-pass action; the final liquidation isn't logged
+        assert_eq!(lines.len(), 1 + 3);
+        assert!(lines[1].starts_with("1650000000000,buy,0.05,0.5,"));
+        assert!(lines[2].starts_with("1650000002000,sell,"));
+        assert!(lines[3].starts_with("1650000004000,buy,"));
+        // an action of zero quantity, like a fraction of an empty balance, isn't a fill
+        let actions = vec![
+            TradeAction::SellFraction { fraction: 1.0 },
+            TradeAction::BuyQuote { base_quantity: 0.0 },
+            TradeAction::BuyQuote { base_quantity: 0.5 },
+        ];
+        let result = run_scripted(actions, &[0.05; 3], &execution(0.001), &simulation);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].action, "buy");
+        assert_eq!(result.trades[0].quantity, 0.5);
+    }
+
+    #[test]
+    fn actions_inside_the_cooldown_are_suppressed() {
+        let mut simulation = simulation();
+        simulation.cooldown = 3;
+        simulation.record_trades = true;
+        let result = executor(&[0.05; 6]).simulate_range::<BuySellBuy>(
+            0,
+            6,
+            &execution(0.0),
+            &strategy_config(),
+            &simulation,
+        );
+        // fills on 0 and 4; the sell on 2 comes too soon after the first buy
+        assert_eq!(result.suppressed_actions, 1);
+        let fill_times: Vec<i64> = result
+            .trades
+            .iter()
+            .map(|trade| (trade.time_milliseconds - 1_650_000_000_000) / 1000)
+            .collect();
+        assert_eq!(fill_times, vec![0, 4]);
+        // an action of zero quantity trades nothing, so it doesn't start a cooldown
+        let actions = vec![
+            TradeAction::SellFraction { fraction: 1.0 },
+            TradeAction::BuyQuote { base_quantity: 0.5 },
+        ];
+        let result = run_scripted(actions, &[0.05; 2], &execution(0.0), &simulation);
+        assert_eq!(result.suppressed_actions, 0);
+        assert_eq!(result.trades.len(), 1);
+    }
+
+    #[test]
+    fn annualized_return_compounds_over_the_span() {
+        let year = MILLISECONDS_PER_YEAR as i64;
+        // +21% over two years is +10% a year
+        let two_years = annualized_return(1.0, 1.21, 2 * year).unwrap();
+        assert!((two_years - 0.1).abs() < 1e-9);
+        // +1% over a quarter is a bit over +4% a year
+        let quarter = annualized_return(2.0, 2.02, year / 4).unwrap();
+        assert!((quarter - (1.01f64.powi(4) - 1.0)).abs() < 1e-9);
+        // too short to annualize, and nothing to grow from
+        assert_eq!(annualized_return(1.0, 1.01, 3600 * 1000), None);
+        assert_eq!(annualized_return(0.0, 1.0, year), None);
+    }
+
+    #[test]
+    fn fractions_trade_that_share_of_the_current_balance() {
+        let balance = Balance {
+            base_balance: 3.0,
+            quote_balance: 0.1,
+        };
+        match (TradeAction::BuyFraction { fraction: 0.5 }).resolved(&balance) {
+            TradeAction::BuyQuote { base_quantity } => assert_eq!(base_quantity, 1.5),
+            _ => panic!("a buy fraction resolves to a buy"),
+        }
+        match (TradeAction::SellFraction { fraction: 0.5 }).resolved(&balance) {
+            TradeAction::SellQuote { quote_quantity } => assert_eq!(quote_quantity, 0.05),
+            _ => panic!("a sell fraction resolves to a sell"),
+        }
+        // capping resolves first, so max_alloc applies to the resolved quantity
+        match (TradeAction::BuyFraction { fraction: 0.5 }).capped(&balance, 0.25) {
+            TradeAction::BuyQuote { base_quantity } => assert_eq!(base_quantity, 0.75),
+            _ => panic!("a capped buy fraction is a buy"),
+        }
+    }
+
+    #[test]
+    fn buy_fee_bps_takes_exactly_that_share_of_the_fill() {
+        let fee_model = FeeSpec {
+            buy_bps: 10.0,
+            sell_bps: 0.0,
+            discount: 0.0,
+        };
+        let mut balance = simulation().start_balance;
+        balance.buy(1.0, &fee_model, false, 0.05);
+        // 0.1% less than the fee-free 0.05
+        assert!((balance.quote_balance - 0.05 * 0.999).abs() < 1e-15);
+        // no fee on the sell side
+        let quote = balance.quote_balance;
+        balance.sell(quote, &fee_model, false, 0.05);
+        assert!((balance.base_balance - 0.999).abs() < 1e-12);
+    }
+
+    #[test]
+    fn best_match_only_drops_other_prints_from_the_price_stream() {
+        let mut trades = trades(&[0.05, 0.09, 0.051, 0.01, 0.052]);
+        trades[1].is_best_match = false;
+        trades[3].is_best_match = false;
+        trades.reverse();
+        let executor = Executor {
+            db: db::Db::from(trades).unwrap(),
+            assets: Assets::from_symbol("ETHBTC"),
+        };
+        let mut simulation = simulation();
+        simulation.record_equity = true;
+        let run = |simulation: &SimulationConfig| {
+            executor
+                .simulate_range::<ScriptedStrategy>(
+                    0,
+                    5,
+                    &execution(0.0),
+                    &strategy_config(),
+                    simulation,
+                )
+                .equity_curve
+                .iter()
+                .map(|point| point.mark_price)
+                .collect::<Vec<f64>>()
+        };
+        assert_eq!(run(&simulation), vec![0.05, 0.09, 0.051, 0.01, 0.052]);
+        simulation.best_match_only = true;
+        assert_eq!(run(&simulation), vec![0.05, 0.051, 0.052]);
+    }
+
+    #[test]
+    fn metrics_endpoint_serves_the_run_state() {
+        let metrics = Box::leak(Box::<Metrics>::default());
+        let addr = serve_metrics("127.0.0.1", 0, metrics).unwrap();
+        let mut simulation = simulation();
+        simulation.metrics = Some(metrics);
+        // buys quote with 0.5 base at 0.05, then the price falls to 0.04
+        executor(&[0.05, 0.05, 0.04]).simulate_range::<BuyOnFirstTrade>(
+            0,
+            3,
+            &execution(0.0),
+            &strategy_config(),
+            &simulation,
+        );
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let gauges: HashMap<&str, f64> = body
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.split_once(' ').unwrap();
+                (name, value.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(gauges["hist_executor_runs_total"], 1.0);
+        assert_eq!(gauges["hist_executor_fills_total"], 1.0);
+        assert_eq!(gauges["hist_executor_last_price"], 0.04);
+        // the final liquidation sold the quote back, buying more base at 0.04
+        assert_eq!(gauges["hist_executor_quote_balance"], 0.0);
+        assert!((gauges["hist_executor_base_balance"] - (0.5 + 0.025 / 0.04)).abs() < 1e-12);
+        assert!((gauges["hist_executor_pnl"] - (0.025 / 0.04 - 0.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn holding_without_selling_is_all_unrealized_pnl() {
+        // half the base goes into quote at 0.05, which buys 25% more base back at 0.04
+        let result = executor(&[0.05, 0.045, 0.04]).simulate_range::<BuyOnFirstTrade>(
+            0,
+            3,
+            &execution(0.0),
+            &strategy_config(),
+            &simulation(),
+        );
+        assert_eq!(result.realized_pnl, 0.0);
+        assert!((result.unrealized_pnl - 0.125).abs() < 1e-12);
+        assert!((result.final_equity - 1.125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn balance_labels_come_from_the_symbol() {
+        let balance = Balance {
+            base_balance: 1.5,
+            quote_balance: 0.25,
+        };
+        assert_eq!(
+            Assets::from_symbol("ETHBTC").describe(&balance),
+            "ETH balance: 1.5, BTC balance: 0.25"
+        );
+        assert_eq!(
+            Assets::from_symbol("solusdt").describe(&balance),
+            "SOL balance: 1.5, USDT balance: 0.25"
+        );
+        // unknown quote assets fall back to generic labels
+        assert_eq!(
+            Assets::from_symbol("data").describe(&balance),
+            "base balance: 1.5, quote balance: 0.25"
+        );
+    }
+
+    #[test]
+    fn histogram_bins_known_values() {
+        let values = [0.0, 1.0, 1.5, 2.0, 4.0, 3.0];
+        let counts: Vec<usize> = histogram(&values, 4).iter().map(|bin| bin.2).collect();
+        // [0, 1), [1, 2), [2, 3) and [3, 4], the max in the last
+        assert_eq!(counts, vec![1, 2, 1, 2]);
+        let bins = histogram(&values, 4);
+        assert_eq!((bins[0].0, bins[0].1), (0.0, 1.0));
+        assert_eq!((bins[3].0, bins[3].1), (3.0, 4.0));
+        // equal values all go in the first bucket
+        assert_eq!(
+            histogram(&[1.0, 1.0], 3),
+            vec![(1.0, 1.0, 2), (1.0, 1.0, 0), (1.0, 1.0, 0)]
+        );
+        assert!(histogram(&[], 3).is_empty());
+        assert!(histogram(&values, 0).is_empty());
+    }
+
+    #[test]
+    fn every_input_file_is_run_and_summarized() {
+        let dir = std::env::temp_dir().join(format!("hist_executor_{}_inputs", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let inputs = [
+            dir.join("ETHBTC.json"),
+            dir.join("MISSING.json"),
+            dir.join("BNBBTC.json"),
+        ];
+        for (input, price) in [(&inputs[0], 0.05), (&inputs[2], 0.01)] {
+            let mut trades = trades(&[price; 10]);
+            trades.reverse(); // the db keeps the newest trade first
+            db::Db::from(trades).unwrap().save(input).unwrap();
+        }
+        // the way run goes over --input: files that don't load are reported and skipped
+        let mut aggregate = RunSummary::default();
+        let mut processed = Vec::new();
+        for input in &inputs {
+            let symbol = symbol_from_path(input);
+            let executor = match Executor::new(input, &symbol, db::BadPricePolicy::Reject) {
+                Ok(executor) => executor,
+                Err(_) => continue,
+            };
+            let mut summary = RunSummary::default();
+            for _ in 0..3 {
+                let result = executor
+                    .simulate_picked(
+                        Executor::simulate_range::<RandomStrategy>,
+                        &execution(0.0),
+                        &strategy_config(),
+                        &simulation(),
+                    )
+                    .unwrap();
+                summary.add(&result);
+            }
+            assert_eq!(summary.total_count, 3);
+            aggregate.extend(&summary);
+            processed.push(executor.assets.base);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(processed, vec!["ETH", "BNB"]);
+        assert_eq!(aggregate.total_count, 6);
+        assert_eq!(aggregate.final_balances.len(), 6);
+    }
+
+    #[test]
+    fn random_strategy_over_hand_built_trades() {
+        let trades = trades(&[0.05; 20]);
+        let simulation = simulation();
+        let mut strategy = RandomStrategy::new(simulation.start_balance, 0.0, &strategy_config());
+        let result = run_strategy_over(
+            strategy.as_mut(),
+            &trades,
+            &execution(0.0),
+            &simulation,
+            &Assets::from_symbol("ETHBTC"),
+        );
+        // no fees and a flat price: whatever it trades, nothing is won or lost
+        assert!((result.start_base_balance - 1.0).abs() < 1e-12);
+        assert!((result.final_equity - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn apply_action_buys_sells_and_passes() {
+        let fee = FlatFee { fee: 0.001 };
+        let mut balance = simulation().start_balance;
+        let buy = TradeAction::BuyQuote { base_quantity: 0.4 };
+        assert_eq!(
+            apply_action(&mut balance, buy, &fee, false, 0.05),
+            Some(("buy", 0.4))
+        );
+        assert!((balance.base_balance - 0.6).abs() < 1e-12);
+        assert!((balance.quote_balance - 0.4 * 0.05 * 0.999).abs() < 1e-15);
+        let before = balance;
+        assert_eq!(
+            apply_action(&mut balance, TradeAction::Pass, &fee, false, 0.05),
+            None
+        );
+        assert_eq!(
+            (balance.base_balance, balance.quote_balance),
+            (before.base_balance, before.quote_balance)
+        );
+        let quote = balance.quote_balance;
+        let sell = TradeAction::SellQuote {
+            quote_quantity: quote,
+        };
+        assert_eq!(
+            apply_action(&mut balance, sell, &fee, false, 0.04),
+            Some(("sell", quote))
+        );
+        assert_eq!(balance.quote_balance, 0.0);
+        assert!((balance.base_balance - (0.6 + quote / 0.04 * 0.999)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn final_liquidation_can_be_turned_off() {
+        let buy = || vec![TradeAction::BuyQuote { base_quantity: 1.0 }];
+        let prices = [0.05, 0.04];
+        let mut simulation = simulation();
+        let liquidated = run_scripted(buy(), &prices, &execution(0.001), &simulation);
+        simulation.final_liquidation = false;
+        let held = run_scripted(buy(), &prices, &execution(0.001), &simulation);
+        // selling the quote at the end pays the fee once more
+        assert!(held.final_equity > liquidated.final_equity);
+        let marked = 1.0 * 0.05 * 0.999 / 0.04;
+        assert!((held.final_equity - marked).abs() < 1e-12);
+        assert!((liquidated.final_equity - marked * 0.999).abs() < 1e-12);
+    }
+
+    #[test]
+    fn wilson_interval_matches_reference_values() {
+        let close = |(lower, upper): (f64, f64), expected: (f64, f64)| {
+            (lower - expected.0).abs() < 1e-4 && (upper - expected.1).abs() < 1e-4
+        };
+        // 95% intervals, to four decimals
+        assert!(close(
+            wilson_interval(5, 10, Z_95).unwrap(),
+            (0.2366, 0.7634)
+        ));
+        assert!(close(wilson_interval(0, 10, Z_95).unwrap(), (0.0, 0.2775)));
+        assert!(close(wilson_interval(10, 10, Z_95).unwrap(), (0.7225, 1.0)));
+        assert!(close(
+            wilson_interval(73, 100, Z_95).unwrap(),
+            (0.6357, 0.8073)
+        ));
+        assert_eq!(wilson_interval(0, 0, Z_95), None);
+    }
+
+    #[test]
+    fn events_follow_the_run() {
+        let mut simulation = simulation();
+        simulation.record_events = true;
+        simulation.cooldown = 2;
+        let actions = vec![
+            TradeAction::BuyQuote { base_quantity: 0.5 },
+            TradeAction::SellQuote {
+                quote_quantity: 0.01,
+            },
+        ];
+        let result = run_scripted(actions, &[0.05, 0.05, 0.04], &execution(0.0), &simulation);
+        // as they'd be written by --events-out
+        let events: Vec<(String, i64)> = result
+            .events
+            .iter()
+            .map(|event| {
+                let json = serde_json::to_value(event).unwrap();
+                let time = json["time_milliseconds"].as_i64().unwrap();
+                (
+                    json["type"].as_str().unwrap().to_string(),
+                    (time - 1_650_000_000_000) / 1000,
+                )
+            })
+            .collect();
+        // the sell comes within the cooldown of the buy
+        assert_eq!(
+            events,
+            vec![
+                ("Decision".to_string(), 0),
+                ("Fill".to_string(), 0),
+                ("Decision".to_string(), 1),
+                ("Rejected".to_string(), 1),
+                ("Liquidation".to_string(), 2),
+            ]
+        );
+        match result.events.last() {
+            Some(Event::Liquidation {
+                price,
+                quote_quantity,
+                ..
+            }) => {
+                assert_eq!(*price, 0.04);
+                assert!((quote_quantity - 0.5 * 0.05).abs() < 1e-12);
+            }
+            _ => panic!("the run should end with the liquidation"),
+        }
+    }
+
+    #[test]
+    fn comparison_has_a_row_per_strategy() {
+        let executor = executor(&[0.05, 0.051, 0.052, 0.05, 0.049, 0.05, 0.053, 0.055]);
+        let strategies: Vec<(String, RangeSimulateFn)> = ["random", "momentum"]
+            .iter()
+            .map(|name| (name.to_string(), range_simulate_fn(name).unwrap()))
+            .collect();
+        let rows = comparison_rows(
+            &executor,
+            &strategies,
+            3,
+            &execution(0.001),
+            &strategy_config(),
+            &simulation(),
+        )
+        .unwrap();
+        let names: Vec<&str> = rows.iter().map(|row| row.name.as_str()).collect();
+        assert_eq!(names, vec!["random", "momentum"]);
+        for row in &rows {
+            assert!(row.mean_final_equity > 0.0);
+            if let Some(sharpe) = row.sharpe {
+                assert!(sharpe.is_finite());
+            }
+            assert!(row.mean_max_drawdown >= 0.0);
+            assert!((0.0..=1.0).contains(&row.win_rate));
+        }
+    }
+
+    #[test]
+    fn identical_returns_have_no_sharpe() {
+        // momentum never enters on a flat price, so every run returns exactly 0
+        let executor = executor(&[0.05; 8]);
+        let strategies = vec![(
+            "momentum".to_string(),
+            range_simulate_fn("momentum").unwrap(),
+        )];
+        let rows = comparison_rows(
+            &executor,
+            &strategies,
+            3,
+            &execution(0.0),
+            &strategy_config(),
+            &simulation(),
+        )
+        .unwrap();
+        assert!(rows[0].sharpe.is_none());
+    }
+
+    #[test]
+    fn vol_adaptive_waits_out_the_same_move_when_volatile() {
+        // both end 3% above where the window of 3 returns starts
+        let calm = [1.0, 1.0, 1.0, 1.03];
+        let volatile = [1.0, 1.1, 0.95, 1.03];
+        let balance = simulation().start_balance;
+        let config = strategy_config();
+        // calm: threshold 0.01 + 0.014 volatility, below the 3% move
+        assert_eq!(
+            sides(VolAdaptiveStrategy::new(balance, 0.0, &config), &calm),
+            vec!["pass", "pass", "pass", "buy"]
+        );
+        // volatile: threshold 0.01 + 0.108 volatility
+        assert_eq!(
+            sides(VolAdaptiveStrategy::new(balance, 0.0, &config), &volatile),
+            vec!["pass"; 4]
+        );
+        // without sensitivity to volatility both would buy
+        let mut config = strategy_config();
+        config.vol_sensitivity = 0.0;
+        assert_eq!(
+            sides(VolAdaptiveStrategy::new(balance, 0.0, &config), &volatile)[3],
+            "buy"
+        );
+    }
+
+    #[test]
+    fn an_in_the_money_start_position_realizes_its_gain() {
+        let sell_all = || {
+            vec![TradeAction::SellQuote {
+                quote_quantity: 0.06,
+            }]
+        };
+        let mut simulation = simulation();
+        simulation.final_liquidation = false;
+        // the same quote, once taken as bought at the first price
+        let mut cash = simulation;
+        cash.start_balance.quote_balance = 0.06;
+        let from_cash = run_scripted(sell_all(), &[0.05, 0.05], &execution(0.0), &cash);
+        // and once as a position entered at 0.06, which 0.05 puts in the money
+        simulation.start_position = Some(Position {
+            quantity: 0.06,
+            entry_price: 0.06,
+        });
+        let from_position = run_scripted(sell_all(), &[0.05, 0.05], &execution(0.0), &simulation);
+        assert!(from_cash.realized_pnl.abs() < 1e-12);
+        assert!((from_position.realized_pnl - 0.2).abs() < 1e-12);
+        assert!((from_position.final_equity - from_cash.final_equity).abs() < 1e-12);
+    }
+
+    #[test]
+    fn excursions_of_a_round_trip_with_a_known_path() {
+        let actions = vec![
+            TradeAction::BuyQuote { base_quantity: 1.0 },
+            TradeAction::Pass,
+            TradeAction::Pass,
+            TradeAction::Pass,
+            TradeAction::SellQuote {
+                quote_quantity: 0.05,
+            },
+        ];
+        // entered at 0.05, high 0.055, low 0.04, exited at 0.045; 0.02 comes after the exit
+        let prices = [0.05, 0.055, 0.04, 0.045, 0.045, 0.02];
+        let result = run_scripted(actions, &prices, &execution(0.0), &simulation());
+        assert_eq!(result.round_trips.len(), 1);
+        let round_trip = &result.round_trips[0];
+        // holding quote, the low is the best case and the high the worst
+        assert!((round_trip.max_favorable_excursion - (0.05 / 0.04 - 1.0)).abs() < 1e-12);
+        assert!((round_trip.max_adverse_excursion - (0.05 / 0.055 - 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn plot_is_a_non_empty_svg() {
+        let mut simulation = simulation();
+        simulation.record_equity = true;
+        let buy = vec![TradeAction::BuyQuote { base_quantity: 0.5 }];
+        let prices = [0.05, 0.052, 0.049, 0.051, 0.05];
+        let result = run_scripted(buy, &prices, &execution(0.001), &simulation);
+        let path =
+            std::env::temp_dir().join(format!("hist_executor_{}_plot.svg", std::process::id()));
+        result.save_plot_svg(&path).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.trim_start().starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("<polyline"));
+        // nothing recorded, nothing to plot
+        simulation.record_equity = false;
+        let result = run_scripted(Vec::new(), &prices, &execution(0.001), &simulation);
+        assert!(result.save_plot_svg(&path).is_err());
+    }
+
+    #[test]
+    fn rolling_sharpe_drops_in_the_bad_segment() {
+        // steady gains of 1-2% per tick, then steady losses of the same size
+        let returns = [
+            0.02, 0.01, 0.02, 0.01, 0.02, 0.01, -0.02, -0.01, -0.02, -0.01, -0.02, -0.01,
+        ];
+        let mut equity = 1.0;
+        let mut curve = vec![1.0];
+        for ret in returns {
+            equity *= 1.0 + ret;
+            curve.push(equity);
+        }
+        let curve: Vec<EquityPoint> = curve
+            .iter()
+            .enumerate()
+            .map(|(i, &equity)| EquityPoint {
+                time_milliseconds: i as i64,
+                base_balance: equity,
+                quote_balance: 0.0,
+                mark_price: 1.0,
+            })
+            .collect();
+        let sharpes = rolling_sharpe(&curve, 4);
+        // one per window end, from the 4th return on
+        assert_eq!(sharpes.len(), returns.len() - 3);
+        assert_eq!(sharpes[0].0, 4);
+        // mean 0.015 and standard deviation 0.005 per window
+        assert!((sharpes[0].1 - 3.0).abs() < 1e-9);
+        assert!((sharpes[sharpes.len() - 1].1 + 3.0).abs() < 1e-9);
+        assert!(sharpes.windows(2).all(|pair| pair[1].1 <= pair[0].1 + 1e-9));
+        assert!(rolling_sharpe(&curve, 0).is_empty());
+    }
+
+    #[test]
+    fn maker_rebate_increases_received_quantity() {
+        let start = Balance {
+            base_balance: 1.0,
+            quote_balance: 10.0,
+        };
+        let rebate = MakerTakerFee {
+            maker_fee: -0.0002,
+            taker_fee: 0.001,
+            discount: 0.25,
+        };
+        // the discount is taken off the taker fee but leaves the rebate whole
+        let maker_fee = rebate.fee_for(&TradeAction::Pass, true);
+        assert_eq!(maker_fee, -0.0002);
+        assert!((rebate.fee_for(&TradeAction::Pass, false) - 0.00075).abs() < 1e-15);
+        let no_fee = FlatFee { fee: 0.0 };
+        let (mut with_rebate, mut without) = (start, start);
+        with_rebate.buy(0.5, &rebate, true, 0.05);
+        without.buy(0.5, &no_fee, true, 0.05);
+        assert!(with_rebate.quote_balance > without.quote_balance);
+        let (mut with_rebate, mut without) = (start, start);
+        with_rebate.sell(5.0, &rebate, true, 0.05);
+        without.sell(5.0, &no_fee, true, 0.05);
+        assert!(with_rebate.base_balance > without.base_balance);
+        // the same fill as a taker pays the fee instead
+        let mut taker = start;
+        taker.sell(5.0, &rebate, false, 0.05);
+        assert!(taker.base_balance < without.base_balance);
+    }
+
+    #[test]
+    fn runs_exactly_trade_count_trades() {
+        let executor = executor(&[0.05; 10]);
+        let mut simulation = simulation();
+        simulation.trade_count = Some(4);
+        for _ in 0..100 {
+            let (start_id, finish_id) = executor.pick_range(&simulation).unwrap();
+            // fewer only when the run hits the end of the data
+            assert_eq!(finish_id - start_id, usize::min(4, 10 - start_id));
+        }
+        assert!(parse_trade_count("0").is_err());
+        assert_eq!(parse_trade_count("4"), Ok(4));
+    }
+
+    #[test]
+    fn picked_ranges_are_never_empty() {
+        let executor = executor(&[0.05, 0.06]);
+        let simulation = simulation();
+        for _ in 0..100 {
+            let (start_id, finish_id) = executor.pick_range(&simulation).unwrap();
+            assert!(start_id < finish_id && finish_id <= 2);
+        }
+    }
+
+    #[test]
+    fn replay_from_a_saved_manifest_is_bit_identical() {
+        let dir = std::env::temp_dir().join(format!("hist_executor_{}_replay", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("ETHBTC.json");
+        let prices = [
+            0.050, 0.052, 0.049, 0.051, 0.055, 0.047, 0.046, 0.053, 0.058, 0.050, 0.044, 0.049,
+        ];
+        db::Db::from_trades_unsorted(trades(&prices))
+            .unwrap()
+            .save(&input)
+            .unwrap();
+        let simulation = SimulationConfig {
+            record_equity: true,
+            record_trades: true,
+            ..simulation()
+        };
+        let manifest = RunManifest {
+            input: input.clone(),
+            symbol: "ETHBTC".to_string(),
+            strategy: "momentum".to_string(),
+            start_id: 1,
+            finish_id: prices.len(),
+            skip_bad_prices: false,
+            fees: FeeSettings {
+                fee: 0.001,
+                maker_fee: None,
+                taker_fee: None,
+                buy_fee_bps: None,
+                sell_fee_bps: None,
+                discount: 0.0,
+                maker_fills: false,
+            },
+            slippage_bps: 3.0,
+            config: strategy_config(),
+            simulation,
+        };
+        let manifest_path = dir.join("manifest.json");
+        manifest.save(&manifest_path).unwrap();
+
+        // the run as recorded, then rerun from the file
+        let executor = Executor::new(&input, "ETHBTC", db::BadPricePolicy::Reject).unwrap();
+        let execution = ExecutionModel {
+            fee_model: manifest.fees.fee_model(),
+            slippage_model: Box::new(FixedBpsSlippage { bps: 3.0 }),
+            maker_fills: false,
+            order_book: None,
+        };
+        let original = Executor::simulate_range::<MomentumStrategy>(
+            &executor,
+            1,
+            prices.len(),
+            &execution,
+            &manifest.config,
+            &simulation,
+        );
+        let replayed = replay(&manifest_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!original.trades.is_empty());
+        assert_eq!(replayed.trades.len(), original.trades.len());
+        assert_eq!(
+            replayed.final_equity.to_bits(),
+            original.final_equity.to_bits()
+        );
+        let bits = |result: &BacktestResult| -> Vec<u64> {
+            result
+                .equity_curve
+                .iter()
+                .map(|p| p.equity().to_bits())
+                .collect()
+        };
+        assert_eq!(bits(&replayed), bits(&original));
+        assert_eq!(bits(&original).len(), prices.len() - 1);
+    }
+
+    #[test]
+    fn returns_degrade_as_fees_rise() {
+        // a choppy series keeps momentum flipping between buying and selling
+        let prices: Vec<f64> = (0..40)
+            .map(|i| if i % 4 < 2 { 0.050 } else { 0.053 } + i as f64 * 0.0001)
+            .collect();
+        let executor = executor(&prices);
+        let rows = fee_sweep_rows(
+            &executor,
+            Executor::simulate_range::<MomentumStrategy>,
+            &[0.0, 5.0, 10.0, 25.0, 50.0],
+            3,
+            0.0,
+            &strategy_config(),
+            // the whole series, so every run trades
+            &SimulationConfig {
+                time_window: Some((1_650_000_000_000, 1_650_000_100_000)),
+                ..simulation()
+            },
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[2].fee_bps, 10.0);
+        // the same windows at every fee, so only the fees differ
+        assert!(rows
+            .iter()
+            .all(|row| row.mean_start_equity == rows[0].mean_start_equity));
+        assert!(rows
+            .windows(2)
+            .all(|pair| pair[1].mean_final_equity < pair[0].mean_final_equity));
+    }
+
+    #[test]
+    fn orders_below_min_notional_are_skipped() {
+        let mut simulation = simulation();
+        simulation.min_notional = 0.01; // BTC, at 0.05 BTC per ETH
+        simulation.final_liquidation = false;
+        simulation.record_trades = true;
+        let actions = vec![
+            TradeAction::BuyQuote { base_quantity: 0.1 },
+            TradeAction::BuyQuote { base_quantity: 0.5 },
+            TradeAction::SellQuote {
+                quote_quantity: 0.005,
+            },
+        ];
+        let result = run_scripted(actions, &[0.05; 5], &execution(0.0), &simulation);
+        assert_eq!(result.below_min_notional, 2);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].quantity, 0.5);
+    }
+
+    #[test]
+    fn pairs_enter_at_extreme_z_scores_and_exit_at_reversion() {
+        // b drifts up while a follows it up to a stationary log spread, which
+        // jumps up once and down once and returns to zero after each
+        let spreads = [0.0, 0.01, -0.01, 0.0, 0.01, 0.1, 0.0, -0.1, 0.0];
+        let b_prices: Vec<f64> = (0..spreads.len())
+            .map(|i| 0.05 * (1.0 + 0.01 * i as f64))
+            .collect();
+        let a_prices: Vec<f64> = b_prices
+            .iter()
+            .zip(spreads)
+            .map(|(b, s)| b * f64::exp(s))
+            .collect();
+        let (a, b) = (trades(&a_prices), trades(&b_prices));
+        let mut strategy = PairsStrategy::new(5, 1.5, 0.8);
+        let positions: Vec<PairPosition> = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| strategy.react_to_data(a, b))
+            .collect();
+        let expected = [
+            PairPosition::Flat,
+            PairPosition::Flat,
+            PairPosition::Flat,
+            PairPosition::Flat,
+            PairPosition::Flat,  // a full window, but nothing extreme
+            PairPosition::HoldA, // a stretched up
+            PairPosition::Flat,
+            PairPosition::HoldB, // a stretched down, so b up relative to it
+            PairPosition::Flat,
+        ];
+        assert!(positions == expected);
+
+        // the same decisions through the runner, which moves the balance each time
+        let a = db::Db::from_trades_unsorted(a).unwrap();
+        let b = db::Db::from_trades_unsorted(b).unwrap();
+        let result = run_pair_strategy(
+            &mut PairsStrategy::new(5, 1.5, 0.8),
+            &a,
+            &b,
+            &FlatFee { fee: 0.0 },
+            1.0,
+        );
+        assert_eq!(result.position_changes, 4);
+        assert_eq!(result.start_base_balance, 1.0);
+        // both trades were bets on the stretched leg coming back, and it did
+        assert!(result.final_equity > 1.0);
+    }
+
+    #[test]
+    fn trading_stops_once_the_fee_cap_is_hit() {
+        let buy = TradeAction::BuyQuote { base_quantity: 0.5 };
+        let sell = TradeAction::SellQuote {
+            quote_quantity: 0.5,
+        };
+        // a strategy that flips back and forth on every trade
+        let actions: Vec<TradeAction> = (0..10)
+            .map(|i| if i % 2 == 0 { buy } else { sell })
+            .collect();
+        let capped = SimulationConfig {
+            max_total_fees: Some(0.012),
+            record_trades: true,
+            record_events: true,
+            ..simulation()
+        };
+        // each fill pays about 0.005 base in fees at 1%, so the third reaches the cap
+        let result = run_scripted(actions.clone(), &[1.0; 12], &execution(0.01), &capped);
+        assert_eq!(result.trades.len(), 3);
+        assert_eq!(result.over_fee_cap, 7);
+        let rejected = result
+            .events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    Event::Rejected {
+                        reason: "max_total_fees",
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(rejected, 7);
+        // without the cap every action fills
+        let recording = SimulationConfig {
+            record_trades: true,
+            ..simulation()
+        };
+        let uncapped = run_scripted(actions, &[1.0; 12], &execution(0.01), &recording);
+        assert_eq!(uncapped.trades.len(), 10);
+        assert_eq!(uncapped.over_fee_cap, 0);
+    }
+
+    #[test]
+    fn decisions_after_reload_match_an_uninterrupted_run() {
+        let prices = [
+            1.0, 1.02, 1.05, 1.08, 1.04, 1.0, 0.97, 1.01, 1.06, 1.1, 1.05, 0.99, 0.95, 1.0, 1.07,
+        ];
+        let trades = trades(&prices);
+        let balance = simulation().start_balance;
+        let config = strategy_config();
+        for name in [
+            "momentum",
+            "rsi",
+            "trailing-stop",
+            "static-avg",
+            "vol-adaptive",
+        ] {
+            let mut uninterrupted = new_strategy(name, balance, 0.0, &config).unwrap();
+            let mut restarted = new_strategy(name, balance, 0.0, &config).unwrap();
+            for trade in &trades[..6] {
+                uninterrupted.react_to_data(balance, trade);
+                restarted.react_to_data(balance, trade);
+            }
+            let state = restarted.save_state().unwrap();
+            let mut reloaded = new_strategy(name, balance, 0.0, &config).unwrap();
+            reloaded.load_state(&state).unwrap();
+            for trade in &trades[6..] {
+                let expected = uninterrupted.react_to_data(balance, trade);
+                let action = reloaded.react_to_data(balance, trade);
+                assert_eq!(
+                    serde_json::to_value(action).unwrap(),
+                    serde_json::to_value(expected).unwrap(),
+                    "{name}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn signals_only_records_signals_and_leaves_the_balance_alone() {
+        let actions = vec![
+            TradeAction::BuyQuote { base_quantity: 0.5 },
+            TradeAction::Pass,
+            TradeAction::SellQuote {
+                quote_quantity: 1.0,
+            },
+        ];
+        let simulation = SimulationConfig {
+            record_signals: true,
+            record_trades: true,
+            signals_only: true,
+            ..simulation()
+        };
+        let prices = [0.05, 0.06, 0.04, 0.05];
+        let result = run_scripted(actions, &prices, &execution(0.001), &simulation);
+        let signals: Vec<&str> = result.signals.iter().map(|s| s.signal).collect();
+        assert_eq!(signals[..3], ["buy", "pass", "sell"]);
+        assert_eq!(
+            result.signals[0].time_milliseconds,
+            trades(&prices)[0].time_milliseconds
+        );
+        // nothing was filled, not even a final liquidation
+        assert!(result.trades.is_empty());
+        assert_eq!(result.final_equity, result.start_base_balance);
+
+        let path =
+            std::env::temp_dir().join(format!("hist_executor_{}_signals.csv", std::process::id()));
+        result.save_signals_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "timestamp,signal,indicator");
+        assert_eq!(lines.len(), result.signals.len() + 1);
+        assert_eq!(
+            lines[1],
+            format!("{},buy,", result.signals[0].time_milliseconds)
+        );
+    }
+}
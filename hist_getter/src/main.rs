@@ -1,54 +1,487 @@
-use chrono::NaiveDateTime;
-use db;
 use error_chain::error_chain;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use structopt::StructOpt;
+use tokio::sync::Semaphore;
 
 error_chain! {
     links {
         Utils(db::Error, db::ErrorKind);
     }
+    foreign_links {
+        Io(std::io::Error);
+    }
 }
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "example", about = "An example of StructOpt usage.")]
-struct Opt {
-    #[structopt(short = "i", long = "input", parse(from_os_str))]
-    input: PathBuf,
-    #[structopt(short = "c", long = "count")]
-    count: i64,
+struct BackfillOpt {
+    #[structopt(short = "f", long = "file", parse(from_os_str))]
+    file: PathBuf,
     #[structopt(short = "s", long = "symbol", default_value = "ETHBTC")]
-    symbol: String,
+    symbol: db::Symbol,
+    #[structopt(long = "until-id")]
+    until_id: Option<i64>, // stop once this trade id is reached
+    // milliseconds since epoch or a UTC datetime like 2022-05-15T11:32:27Z
+    #[structopt(long = "until-time", parse(try_from_str = db::parse_time))]
+    until_time: Option<i64>, // only used to print an estimate of the pages needed
+    #[structopt(short = "c", long = "count")]
+    count: Option<i64>, // max number of requests; one if neither this nor --until-id is set
+    #[structopt(short = "l", long = "limit", default_value = "1000")]
+    limit: i64, // trades per request, 1..=1000
+    #[structopt(long = "agg")]
+    agg: bool, // use aggTrades endpoint; much smaller responses, same price action
+    #[structopt(long = "dry-run")]
+    dry_run: bool, // only check the api is reachable and print the requests the backfill would make
+    #[structopt(long = "resume")]
+    resume: bool, // save after every page, so rerunning continues where a crash left off
+    #[structopt(long = "weight-threshold", default_value = "960")]
+    weight_threshold: i64, // wait for the next minute once binance reports this much used weight
+    #[structopt(long = "pretty")]
+    pretty: bool, // indented json output, for small files inspected by hand
 }
 
-async fn run() -> Result<()> {
-    let opt = Opt::from_args();
-    let mut db = db::Db::new(&opt.input)?;
+#[derive(Debug, StructOpt)]
+struct BackfillManyOpt {
+    #[structopt(short = "d", long = "dir", parse(from_os_str))]
+    dir: PathBuf, // each symbol goes to its own existing <dir>/<SYMBOL>.json
+    #[structopt(short = "s", long = "symbol", required = true)]
+    symbols: Vec<db::Symbol>,
+    #[structopt(short = "c", long = "count", default_value = "1")]
+    count: i64, // requests per symbol
+    #[structopt(short = "l", long = "limit", default_value = "1000")]
+    limit: i64, // trades per request, 1..=1000
+    #[structopt(long = "agg")]
+    agg: bool, // use aggTrades endpoint; much smaller responses, same price action
+    #[structopt(long = "concurrency", default_value = "4")]
+    concurrency: usize, // symbols backfilled at the same time
+    #[structopt(long = "weight-threshold", default_value = "960")]
+    weight_threshold: i64, // request weight per minute shared by all symbols
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "hist_getter", about = "Historical trade file tool.")]
+enum Command {
+    /// Fetch trades older than the ones already in the file
+    Backfill(BackfillOpt),
+    /// Fetch older trades for several symbols concurrently, sharing the rate limit
+    BackfillMany(BackfillManyOpt),
+    /// Fetch aggregated trades between two times, merging with aggregated trades already in the file
+    FetchRange {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short = "s", long = "symbol", default_value = "ETHBTC")]
+        symbol: db::Symbol,
+        // milliseconds since epoch or a UTC datetime like 2022-05-15T11:32:27Z
+        #[structopt(long = "start", parse(try_from_str = db::parse_time))]
+        start: i64,
+        #[structopt(long = "end", parse(try_from_str = db::parse_time))]
+        end: i64,
+        #[structopt(long = "weight-threshold", default_value = "960")]
+        weight_threshold: i64,
+        #[structopt(long = "pretty")]
+        pretty: bool, // indented json output, for small files inspected by hand
+    },
+    /// Print record count, trade id range, time span and trade sizes of the file
+    Status {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Print record count, trade id range and time span of each file, without loading it fully
+    Summary {
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Check integrity of the file
+    Verify {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Remove repeated trade ids from the file
+    Dedup {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Write the trades of the file as parquet, for analysis in pandas, polars or duckdb
+    ExportParquet {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Keep only the most recent trades of the file
+    Trim {
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        file: PathBuf,
+        #[structopt(short = "k", long = "keep")]
+        keep: usize,
+    },
+}
+
+// writes next to the file and renames over it, so a crash mid-save never
+// leaves a truncated file behind
+fn save_progress(db: &db::Db, file: &Path, pretty: bool) -> Result<()> {
+    let name = file
+        .file_name()
+        .map_or("".into(), |name| name.to_string_lossy());
+    let partial = file.with_file_name(format!(".partial-{name}"));
+    if pretty {
+        db.save_pretty(&partial)?;
+    } else {
+        db.save(&partial)?;
+    }
+    std::fs::rename(&partial, file)?;
+    Ok(())
+}
+
+// time the pages take at the request weight limit, rounded up to whole minutes
+fn minutes_at_rate_limit(pages: i64) -> i64 {
+    let weight = pages * db::HISTORICAL_TRADES_WEIGHT;
+    (weight + db::REQUEST_WEIGHT_PER_MINUTE - 1) / db::REQUEST_WEIGHT_PER_MINUTE
+}
+
+// requests a backfill makes: --count of them, but no more than it takes to
+// reach --until-id; a single one if neither is set
+fn planned_pages(db: &db::Db, opt: &BackfillOpt) -> Result<i64> {
+    Ok(match (opt.count, opt.until_id) {
+        (Some(count), Some(until_id)) => i64::min(count, db.pages_to_reach(until_id, opt.limit)?),
+        (Some(count), None) => count,
+        (None, Some(until_id)) => db.pages_to_reach(until_id, opt.limit)?,
+        (None, None) => 1,
+    })
+}
+
+fn print_progress(db: &db::Db) {
     println!(
         "Id: {}, records count {}, min_ts: {}",
         db.get_min_trade_id(),
         db.get_data_len(),
-        NaiveDateTime::from_timestamp(db.get_min_time_milliseconds() / 1000, 0)
+        db::format_milliseconds(db.get_min_time_milliseconds())
     );
+}
 
-    for i in 0..opt.count {
-        db.load_more_data(&opt.symbol).await?;
+async fn backfill(opt: &BackfillOpt) -> Result<()> {
+    let file = opt.file.as_path();
+    let mut db = db::Db::new(&file)?;
+    let pages = planned_pages(&db, opt)?;
+    print_progress(&db);
+    if let Some(until_time) = opt.until_time {
+        let time_pages = db.estimate_pages_for_time(until_time) as i64;
+        println!(
+            "about {} pages of {} trades needed to reach {}, about {} minutes at the rate limit",
+            time_pages,
+            db::MAX_PAGE_LIMIT,
+            db::format_milliseconds(until_time),
+            minutes_at_rate_limit(time_pages)
+        );
+    }
+    if opt.dry_run {
+        db::ping().await?;
         println!(
-            "Id: {}, records count {}, min_ts: {}",
-            db.get_min_trade_id(),
-            db.get_data_len(),
-            NaiveDateTime::from_timestamp(db.get_min_time_milliseconds() / 1000, 0)
+            "api reachable; {} requests planned, about {} minutes at the rate limit",
+            pages,
+            minutes_at_rate_limit(pages)
         );
+        return Ok(());
+    }
+
+    let fetcher = db::HistoricalTradesFetcher {
+        weight_threshold: opt.weight_threshold,
+    };
+    let agg_fetcher = db::AggTradesFetcher {
+        weight_threshold: opt.weight_threshold,
+    };
+    // on ctrl-c, finish the current page and save instead of dying mid-backfill
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = interrupted.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            handler_interrupted.store(true, Ordering::SeqCst);
+        }
+    });
+    for i in 0..pages {
+        if opt.agg {
+            db.load_more_data(&agg_fetcher, &opt.symbol, opt.limit)
+                .await?;
+        } else {
+            db.load_more_data(&fetcher, &opt.symbol, opt.limit).await?;
+        }
+        print_progress(&db);
+        if opt.resume {
+            save_progress(&db, file, opt.pretty)?;
+        }
         if i % 100 == 0 {
-            println!("Processing {} out out {}", i, opt.count);
+            println!("Processing {} out out {}", i, pages);
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            println!("Interrupted after {} out of {} pages, saving", i + 1, pages);
+            break;
+        }
+    }
+
+    save_progress(&db, file, opt.pretty)?;
+
+    Ok(())
+}
+
+// Request weight budget per minute window shared by concurrent backfills, so
+// together they stay under binance's limit. Counts weight up front instead of
+// trusting X-MBX-USED-WEIGHT, which lags behind requests still in flight.
+struct RateLimiter {
+    budget: i64,
+    used: Mutex<(i64, i64)>, // minute window, weight used in it
+}
+
+impl RateLimiter {
+    fn new(budget: i64) -> RateLimiter {
+        RateLimiter {
+            budget,
+            used: Mutex::new((0, 0)),
+        }
+    }
+    async fn acquire(&self, weight: i64) {
+        loop {
+            let wait_ms = {
+                let mut used = self.used.lock().unwrap();
+                let now = db::local_time_milliseconds();
+                let minute = now / 60_000;
+                if used.0 != minute {
+                    *used = (minute, 0);
+                }
+                if used.1 + weight <= self.budget {
+                    used.1 += weight;
+                    return;
+                }
+                60_000 - now % 60_000
+            };
+            tokio::time::sleep(Duration::from_millis(wait_ms as u64)).await;
         }
     }
+}
+
+async fn backfill_symbol<F: db::TradeFetcher>(
+    symbol: db::Symbol,
+    opt: Arc<BackfillManyOpt>,
+    limiter: Arc<RateLimiter>,
+    semaphore: Arc<Semaphore>,
+    fetcher: Arc<F>,
+) -> Result<()> {
+    let _permit = semaphore.acquire().await.chain_err(|| "semaphore closed")?;
+    let file = opt.dir.join(format!("{symbol}.json"));
+    let mut db = db::Db::new(&file)?;
+    for _ in 0..opt.count {
+        // aggTrades is cheaper, but budgeting both the same keeps this simple
+        limiter.acquire(db::HISTORICAL_TRADES_WEIGHT).await;
+        db.load_more_data(fetcher.as_ref(), &symbol, opt.limit)
+            .await?;
+    }
+    save_progress(&db, &file, false)?;
+    print!("{symbol}: ");
+    print_progress(&db);
+    Ok(())
+}
 
-    db.save(&opt.input)?;
+// one task per symbol, all sharing the rate limit and at most
+// opt.concurrency of them fetching at a time
+fn spawn_backfills<F: db::TradeFetcher + Send + Sync + 'static>(
+    opt: &Arc<BackfillManyOpt>,
+    fetcher: Arc<F>,
+) -> Vec<tokio::task::JoinHandle<Result<()>>> {
+    let limiter = Arc::new(RateLimiter::new(opt.weight_threshold));
+    let semaphore = Arc::new(Semaphore::new(opt.concurrency));
+    opt.symbols
+        .iter()
+        .map(|symbol| {
+            tokio::spawn(backfill_symbol(
+                symbol.clone(),
+                opt.clone(),
+                limiter.clone(),
+                semaphore.clone(),
+                fetcher.clone(),
+            ))
+        })
+        .collect()
+}
 
+async fn backfill_many(opt: BackfillManyOpt) -> Result<()> {
+    let opt = Arc::new(opt);
+    // the limiter does the throttling, so the header based one is turned off
+    let handles = if opt.agg {
+        spawn_backfills(
+            &opt,
+            Arc::new(db::AggTradesFetcher {
+                weight_threshold: i64::MAX,
+            }),
+        )
+    } else {
+        spawn_backfills(
+            &opt,
+            Arc::new(db::HistoricalTradesFetcher {
+                weight_threshold: i64::MAX,
+            }),
+        )
+    };
+    let mut failed = 0;
+    for (symbol, handle) in opt.symbols.iter().zip(handles) {
+        match handle.await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => {
+                println!("{symbol}: error: {e}");
+                failed += 1;
+            }
+            Err(e) => {
+                println!("{symbol}: task failed: {e}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        error_chain::bail!("{} of {} symbols failed", failed, opt.symbols.len());
+    }
+    Ok(())
+}
+
+async fn fetch_range(
+    file: &Path,
+    symbol: &db::Symbol,
+    start: i64,
+    end: i64,
+    weight_threshold: i64,
+    pretty: bool,
+) -> Result<()> {
+    let fetcher = db::AggTradesFetcher { weight_threshold };
+    let mut db = fetcher.fetch_time_range(symbol, start, end).await?;
+    if file.exists() {
+        db = db::Db::merge(db::Db::new(&file)?, db)?;
+    }
+    save_progress(&db, file, pretty)?;
+    print_progress(&db);
     Ok(())
 }
 
+fn status(file: &Path) -> Result<()> {
+    let db = db::Db::new(&file)?;
+    let min_time = db.get_min_time_milliseconds();
+    let max_time = db.get_max_time_milliseconds();
+    println!(
+        "{:?}: records count {}, ids {}-{}, time {} - {} ({} hours)",
+        file,
+        db.get_data_len(),
+        db.get_min_trade_id(),
+        db.get_max_trade_id(),
+        db::format_milliseconds(min_time),
+        db::format_milliseconds(max_time),
+        (max_time - min_time) / 1000 / 3600
+    );
+    let quantities = db.quantity_stats();
+    println!(
+        "trade quantity: mean {}, median {}, p5 {}, p25 {}, p75 {}, p95 {}, p99 {}, min {}, max {}",
+        quantities.mean,
+        quantities.median,
+        quantities.p5,
+        quantities.p25,
+        quantities.p75,
+        quantities.p95,
+        quantities.p99,
+        quantities.min,
+        quantities.max
+    );
+    Ok(())
+}
+
+fn summary(files: &[PathBuf]) -> Result<()> {
+    for file in files {
+        match db::summarize_file(file) {
+            Ok(summary) => println!(
+                "{:?}: records count {}, ids {}-{}, time {} - {}",
+                file,
+                summary.count,
+                summary.min_trade_id,
+                summary.max_trade_id,
+                db::format_milliseconds(summary.min_time_milliseconds),
+                db::format_milliseconds(summary.max_time_milliseconds)
+            ),
+            Err(e) => println!("{:?}: error: {}", file, e),
+        }
+    }
+    Ok(())
+}
+
+fn verify(file: &Path) -> Result<()> {
+    let db = db::Db::new(&file)?;
+    db.validate()?;
+    println!("{:?}: {} records ok", file, db.get_data_len());
+    Ok(())
+}
+
+fn dedup(file: &Path) -> Result<()> {
+    let mut db = db::Db::new(&file)?;
+    let duplicates = db.find_duplicates();
+    if duplicates.is_empty() {
+        println!("{:?}: no duplicate trade ids", file);
+        return Ok(());
+    }
+    println!(
+        "{:?}: {} trade ids appear more than once: {:?}",
+        file,
+        duplicates.len(),
+        duplicates
+    );
+    let before = db.get_data_len();
+    db.dedup();
+    save_progress(&db, file, false)?;
+    println!("{:?}: removed {} records", file, before - db.get_data_len());
+    Ok(())
+}
+
+fn export_parquet(file: &Path, output: &Path) -> Result<()> {
+    let db = db::Db::new(&file)?;
+    db.save_parquet(&output)?;
+    println!(
+        "{:?}: wrote {} records to {:?}",
+        file,
+        db.get_data_len(),
+        output
+    );
+    Ok(())
+}
+
+fn trim(file: &Path, keep: usize) -> Result<()> {
+    let mut db = db::Db::new(&file)?;
+    let before = db.get_data_len();
+    db.trim_to_recent(keep)?;
+    save_progress(&db, file, false)?;
+    println!(
+        "{:?}: kept {} of {} records",
+        file,
+        db.get_data_len(),
+        before
+    );
+    Ok(())
+}
+
+async fn run() -> Result<()> {
+    match Command::from_args() {
+        Command::Backfill(opt) => backfill(&opt).await,
+        Command::BackfillMany(opt) => backfill_many(opt).await,
+        Command::FetchRange {
+            file,
+            symbol,
+            start,
+            end,
+            weight_threshold,
+            pretty,
+        } => fetch_range(&file, &symbol, start, end, weight_threshold, pretty).await,
+        Command::Status { file } => status(&file),
+        Command::Summary { files } => summary(&files),
+        Command::Verify { file } => verify(&file),
+        Command::Dedup { file } => dedup(&file),
+        Command::ExportParquet { file, output } => export_parquet(&file, &output),
+        Command::Trim { file, keep } => trim(&file, keep),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     if let Err(ref e) = run().await {
@@ -67,3 +500,222 @@ async fn main() {
         ::std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_minutes_round_up_to_the_rate_limit() {
+        // 1200 weight a minute at 5 weight a page is 240 pages a minute
+        assert_eq!(minutes_at_rate_limit(0), 0);
+        assert_eq!(minutes_at_rate_limit(1), 1);
+        assert_eq!(minutes_at_rate_limit(240), 1);
+        assert_eq!(minutes_at_rate_limit(241), 2);
+    }
+
+    #[test]
+    fn planned_pages_match_count_and_until_id() {
+        // trades 11 to 20, so 10 more to reach trade 1
+        let db = db::Db::from((11..=20).rev().map(trade).collect()).unwrap();
+        let planned = |args: &[&str]| {
+            let mut all = vec!["backfill", "-f", "ethbtc.json", "-l", "3"];
+            all.extend(args);
+            match parse(&all).unwrap() {
+                Command::Backfill(opt) => planned_pages(&db, &opt).unwrap(),
+                other => panic!("parsed as {:?}", other),
+            }
+        };
+        assert_eq!(planned(&[]), 1);
+        assert_eq!(planned(&["-c", "2"]), 2);
+        assert_eq!(planned(&["--until-id", "1"]), 4);
+        assert_eq!(planned(&["--until-id", "1", "-c", "2"]), 2);
+        assert_eq!(planned(&["--until-id", "1", "-c", "50"]), 4);
+        assert_eq!(planned(&["--until-id", "15", "-c", "50"]), 0);
+        // --until-time is only estimated, it doesn't change what's fetched
+        assert_eq!(
+            planned(&["-c", "2", "--until-time", "2022-05-15T00:00:00Z"]),
+            2
+        );
+        // a planned backfill of 480 pages takes two minutes at the rate limit
+        assert_eq!(minutes_at_rate_limit(480), 2);
+    }
+
+    fn parse(args: &[&str]) -> structopt::clap::Result<Command> {
+        Command::from_iter_safe(std::iter::once("hist_getter").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn subcommands_parse() {
+        match parse(&["backfill", "-f", "ethbtc.json", "--until-id", "340000000"]).unwrap() {
+            Command::Backfill(opt) => {
+                assert_eq!(opt.file, PathBuf::from("ethbtc.json"));
+                assert_eq!(opt.symbol.as_str(), "ETHBTC");
+                assert_eq!(opt.until_id, Some(340000000));
+                assert_eq!(opt.limit, 1000);
+            }
+            other => panic!("parsed as {:?}", other),
+        }
+        match parse(&["status", "--file", "ethbtc.json"]).unwrap() {
+            Command::Status { file } => assert_eq!(file, PathBuf::from("ethbtc.json")),
+            other => panic!("parsed as {:?}", other),
+        }
+        match parse(&["verify", "-f", "ethbtc.json"]).unwrap() {
+            Command::Verify { file } => assert_eq!(file, PathBuf::from("ethbtc.json")),
+            other => panic!("parsed as {:?}", other),
+        }
+        // the file is required, and there is no default subcommand
+        let parse_err = |args: &[&str]| {
+            Command::from_iter_safe(std::iter::once("hist_getter").chain(args.iter().copied()))
+                .is_err()
+        };
+        assert!(parse_err(&["verify"]));
+        assert!(parse_err(&[
+            "backfill",
+            "-f",
+            "ethbtc.json",
+            "--until-id",
+            "soon"
+        ]));
+        assert!(parse_err(&[]));
+    }
+
+    fn trade(trade_id: i64) -> db::HistoricalTrade {
+        db::HistoricalTrade {
+            trade_id,
+            price: "0.05".to_string(),
+            quantity: "1.0".to_string(),
+            quote_quantity: "0.05".to_string(),
+            time_milliseconds: 1_652_614_347_000 + trade_id,
+            is_buyer_maker: false,
+            is_best_match: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn resumed_backfill_ends_contiguous() {
+        let fetcher = db::InMemoryTradeFetcher::new((1..=10).map(trade).collect());
+        let symbol = db::Symbol::new("ETHBTC").unwrap();
+        let file =
+            std::env::temp_dir().join(format!("hist_getter_{}_resume.json", std::process::id()));
+        save_progress(
+            &db::Db::from(vec![trade(10), trade(9)]).unwrap(),
+            &file,
+            false,
+        )
+        .unwrap();
+        // each session picks up from the file the previous one saved after its page
+        for _ in 0..2 {
+            let mut db = db::Db::new(&file).unwrap();
+            db.load_more_data(&fetcher, &symbol, 3).await.unwrap();
+            save_progress(&db, &file, false).unwrap();
+        }
+        let db = db::Db::new(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let ids: Vec<i64> = db.iter_chronological().map(|t| t.trade_id).collect();
+        assert_eq!(ids, (3..=10).collect::<Vec<_>>());
+        assert!(db.validate().is_ok());
+        // the partial file is renamed over the real one, never left behind
+        let partial = file.with_file_name(format!(
+            ".partial-{}",
+            file.file_name().unwrap().to_string_lossy()
+        ));
+        assert!(!partial.exists());
+    }
+
+    // serves pages like InMemoryTradeFetcher, slowly, keeping track of how
+    // many fetches are in flight at once
+    struct CountingFetcher {
+        pages: db::InMemoryTradeFetcher,
+        in_flight: std::sync::atomic::AtomicUsize,
+        max_in_flight: std::sync::atomic::AtomicUsize,
+    }
+
+    impl db::TradeFetcher for CountingFetcher {
+        async fn fetch(
+            &self,
+            symbol: &db::Symbol,
+            from_id: i64,
+            limit: i64,
+        ) -> db::Result<Vec<db::HistoricalTrade>> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.pages.fetch(symbol, from_id, limit).await
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_backfills_respect_the_limit_and_all_finish() {
+        let dir = std::env::temp_dir().join(format!("hist_getter_{}_many", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let symbols = ["ETHBTC", "BNBBTC", "LTCBTC", "XRPBTC", "ADABTC"];
+        for symbol in symbols {
+            let db = db::Db::from(vec![trade(10), trade(9)]).unwrap();
+            db.save(&dir.join(format!("{symbol}.json"))).unwrap();
+        }
+        let mut args = vec![
+            "backfill-many",
+            "-d",
+            dir.to_str().unwrap(),
+            "-c",
+            "2",
+            "-l",
+            "2",
+            "--concurrency",
+            "2",
+        ];
+        for symbol in symbols {
+            args.extend(["-s", symbol]);
+        }
+        let opt = match parse(&args).unwrap() {
+            Command::BackfillMany(opt) => Arc::new(opt),
+            other => panic!("parsed as {:?}", other),
+        };
+        let fetcher = Arc::new(CountingFetcher {
+            pages: db::InMemoryTradeFetcher::new((1..=10).map(trade).collect()),
+            in_flight: Default::default(),
+            max_in_flight: Default::default(),
+        });
+        let handles = spawn_backfills(&opt, fetcher.clone());
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        let lengths: Vec<usize> = symbols
+            .iter()
+            .map(|symbol| {
+                let db = db::Db::new(&dir.join(format!("{symbol}.json"))).unwrap();
+                assert_eq!(db.get_min_trade_id(), 5);
+                db.get_data_len()
+            })
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(lengths, vec![6; symbols.len()]);
+        // the symbols did overlap, but never more than two at a time
+        assert_eq!(fetcher.max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn dedup_removes_repeated_trade_ids_from_the_file() {
+        let file =
+            std::env::temp_dir().join(format!("hist_getter_{}_dedup.json", std::process::id()));
+        let trades = vec![
+            trade(4),
+            trade(3),
+            trade(3),
+            trade(2),
+            trade(2),
+            trade(2),
+            trade(1),
+        ];
+        db::Db::from(trades).unwrap().save(&file).unwrap();
+        assert_eq!(db::Db::new(&file).unwrap().find_duplicates(), vec![3, 2]);
+        dedup(&file).unwrap();
+        let db = db::Db::new(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let ids: Vec<i64> = db.iter_recent_first().map(|t| t.trade_id).collect();
+        assert_eq!(ids, vec![4, 3, 2, 1]);
+        assert!(db.find_duplicates().is_empty());
+    }
+}
@@ -1,12 +1,39 @@
-use db;
 use error_chain::error_chain;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
 error_chain! {
     links {
         Utils(db::Error, db::ErrorKind);
     }
+    foreign_links {
+        ParseFloat(std::num::ParseFloatError);
+    }
+}
+
+// How prices are rewritten. reciprocal and scale keep
+// price * quantity == quote_quantity, so the output is still a valid trade
+// series; log doesn't, and its quantities are copied unchanged.
+#[derive(Debug)]
+enum Transform {
+    Reciprocal, // 1 / price, i.e. the inverted pair; quantity and quote_quantity swap
+    Log,        // natural log of price; prices below 1 become negative
+    Scale,      // price * --scale-factor; quote_quantity scales along
+}
+
+impl FromStr for Transform {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Transform, String> {
+        match s {
+            "reciprocal" => Ok(Transform::Reciprocal),
+            "log" => Ok(Transform::Log),
+            "scale" => Ok(Transform::Scale),
+            other => Err(format!(
+                "unknown transform {other}, expected reciprocal, log or scale"
+            )),
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -16,18 +43,213 @@ struct Opt {
     input: PathBuf,
     #[structopt(short = "o", long = "output", parse(from_os_str))]
     output: PathBuf,
+    #[structopt(long = "transform", default_value = "reciprocal")]
+    transform: Transform,
+    #[structopt(long = "scale-factor")]
+    scale_factor: Option<f64>, // required by --transform scale
+    // In the inverted pair the buyer of the original pair becomes the seller,
+    // so the maker side flips from buyer to seller and vice versa.
+    #[structopt(long = "flip-maker")]
+    flip_maker: bool,
+    #[structopt(long = "backup", parse(from_os_str))]
+    backup: Option<PathBuf>, // optionally keep a copy of the original data
+    #[structopt(long = "skip-bad-prices")]
+    skip_bad_prices: bool, // drop trades with an unparseable or non-positive price instead of failing
+    #[structopt(long = "decimals", default_value = "8")]
+    decimals: usize, // of written prices; binance quotes prices with 8
+    #[structopt(long = "pretty")]
+    pretty: bool, // indented json output, for small files inspected by hand
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
+// rewrites the trades as opt asks, see Transform and --flip-maker
+fn transform(trades: &mut [db::HistoricalTrade], opt: &Opt) -> Result<()> {
+    let format_price = |price: f64| format!("{:.*}", opt.decimals, price);
+    for trade in trades.iter_mut() {
+        let price = trade.try_price()?;
+        match opt.transform {
+            Transform::Reciprocal => {
+                trade.price = format_price(1.0 / price);
+                std::mem::swap(&mut trade.quantity, &mut trade.quote_quantity);
+            }
+            Transform::Log => {
+                trade.price = format_price(price.ln());
+            }
+            Transform::Scale => {
+                let factor = match opt.scale_factor {
+                    Some(factor) => factor,
+                    None => error_chain::bail!("--transform scale needs --scale-factor"),
+                };
+                let quote_quantity: f64 = trade.quote_quantity.parse()?;
+                trade.price = format_price(price * factor);
+                trade.quote_quantity = format!("{}", quote_quantity * factor);
+            }
+        }
+        // rounding a small enough price away would break the trade series
+        if !matches!(opt.transform, Transform::Log) && trade.try_price().is_err() {
+            error_chain::bail!(
+                "price of trade {} rounds to {} at {} decimals, use more --decimals",
+                trade.trade_id,
+                trade.price,
+                opt.decimals
+            );
+        }
+        if opt.flip_maker {
+            trade.is_buyer_maker = !trade.is_buyer_maker;
+        }
+    }
+    Ok(())
+}
+
+fn run(opt: &Opt) -> Result<()> {
     let db = db::Db::new(&opt.input)?;
     let mut trades = db.get_all_data_cloned();
-    for trade in &mut trades {
-        trade.price = format!("{}", 1.0 / trade.get_price());
-        std::mem::swap(&mut trade.quantity, &mut trade.quote_quantity);
+    if opt.skip_bad_prices {
+        let before = trades.len();
+        trades.retain(|trade| trade.try_price().is_ok());
+        println!("skipped {} trades with bad prices", before - trades.len());
+    }
+    transform(&mut trades, opt)?;
+    let new_db = db::Db::from_trades_unsorted(trades)?;
+    if opt.pretty {
+        new_db.save_pretty(&opt.output)?;
+    } else {
+        new_db.save(&opt.output)?;
+    }
+    if let Some(backup) = &opt.backup {
+        db.save(backup)?;
     }
-    let new_db = db::Db::from(trades)?;
-    new_db.save(&opt.output)?;
-    db.save(&"tmp.json")?;
     Ok(())
 }
+
+fn main() -> Result<()> {
+    run(&Opt::from_args())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(
+        trade_id: i64,
+        price: &str,
+        quantity: &str,
+        quote_quantity: &str,
+    ) -> db::HistoricalTrade {
+        db::HistoricalTrade {
+            trade_id,
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+            quote_quantity: quote_quantity.to_string(),
+            time_milliseconds: 1652614347356 + trade_id,
+            is_buyer_maker: trade_id % 2 == 0,
+            is_best_match: true,
+        }
+    }
+
+    fn opt(args: &[&str]) -> Opt {
+        let mut argv = vec!["hist_inverter", "-i", "in.json", "-o", "out.json"];
+        argv.extend(args);
+        Opt::from_iter(argv)
+    }
+
+    #[test]
+    fn reciprocal_inverts_price_swaps_quantities_and_flips_makers() {
+        let original = vec![
+            trade(2, "0.05000000", "2.0", "0.1"),
+            trade(1, "0.04000000", "1.5", "0.06"),
+        ];
+        let mut trades = original.clone();
+        transform(&mut trades, &opt(&["--flip-maker"])).unwrap();
+        for (inverted, original) in trades.iter().zip(&original) {
+            let price = inverted.get_price() * original.get_price();
+            assert!((price - 1.0).abs() < 1e-12);
+            assert_eq!(inverted.quantity, original.quote_quantity);
+            assert_eq!(inverted.quote_quantity, original.quantity);
+            assert_eq!(inverted.is_buyer_maker, !original.is_buyer_maker);
+        }
+        // the price still times the quantity gives the quote quantity
+        let trade = &trades[0];
+        let quantity: f64 = trade.quantity.parse().unwrap();
+        let quote_quantity: f64 = trade.quote_quantity.parse().unwrap();
+        assert!((trade.get_price() * quantity - quote_quantity).abs() < 1e-9);
+        // the maker side only flips when asked to
+        let mut trades = original.clone();
+        transform(&mut trades, &opt(&[])).unwrap();
+        assert_eq!(trades[0].is_buyer_maker, original[0].is_buyer_maker);
+    }
+
+    #[test]
+    fn only_the_requested_files_are_written() {
+        let dir = std::env::temp_dir().join(format!("hist_inverter_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = db::Db::from(vec![
+            trade(2, "0.05", "2.0", "0.1"),
+            trade(1, "0.04", "1.5", "0.06"),
+        ]);
+        db.unwrap().save(&dir.join("in.json")).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let opt = Opt::from_iter([
+            "hist_inverter",
+            "-i",
+            &path("in.json"),
+            "-o",
+            &path("out.json"),
+        ]);
+        run(&opt).unwrap();
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, vec!["in.json", "out.json"]);
+    }
+
+    #[test]
+    fn log_and_scale_transforms() {
+        let original = vec![
+            trade(2, "2.00000000", "3.0", "6.0"),
+            trade(1, "0.50000000", "4.0", "2.0"),
+        ];
+        let mut trades = original.clone();
+        transform(&mut trades, &opt(&["--transform", "log"])).unwrap();
+        assert_eq!(trades[0].price, "0.69314718");
+        assert_eq!(trades[1].price, "-0.69314718");
+        // quantities are copied as they were
+        assert_eq!(trades[1].quantity, "4.0");
+        assert_eq!(trades[1].quote_quantity, "2.0");
+        let mut trades = original.clone();
+        transform(
+            &mut trades,
+            &opt(&["--transform", "scale", "--scale-factor", "10"]),
+        )
+        .unwrap();
+        assert_eq!(trades[0].price, "20.00000000");
+        assert_eq!(trades[0].quantity, "3.0");
+        assert_eq!(trades[0].quote_quantity, "60");
+        assert_eq!(trades[1].price, "5.00000000");
+        // scale needs its factor, and there are no other transforms
+        let mut trades = original.clone();
+        assert!(transform(&mut trades, &opt(&["--transform", "scale"])).is_err());
+        let unknown =
+            Opt::from_iter_safe(["hist_inverter", "-i", "a", "-o", "b", "--transform", "sqrt"]);
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn inverted_prices_have_fixed_decimals_and_parse_back() {
+        let original = vec![trade(2, "0.07000000", "2.0", "0.14")];
+        let mut trades = original.clone();
+        transform(&mut trades, &opt(&[])).unwrap();
+        // 1 / 0.07 = 14.285714285714...
+        assert_eq!(trades[0].price, "14.28571429");
+        assert!((trades[0].get_price() - 1.0 / 0.07).abs() < 1e-8);
+        let mut trades = original.clone();
+        transform(&mut trades, &opt(&["--decimals", "3"])).unwrap();
+        assert_eq!(trades[0].price, "14.286");
+        assert_eq!(trades[0].get_price(), 14.286);
+        // a price that rounds to zero is an error, not a broken trade
+        let mut trades = vec![trade(1, "20000000000", "1.0", "20000000000")];
+        assert!(transform(&mut trades, &opt(&[])).is_err());
+    }
+}